@@ -0,0 +1,215 @@
+use std::time::Duration;
+
+use awedio::sounds::wrappers::Controller;
+use awedio::sounds::SoundMixer;
+use awedio::Sound;
+use bytes::{Buf, Bytes};
+use futures_util::StreamExt;
+use http_body_util::combinators::BoxBody;
+use http_body_util::{BodyExt, Limited};
+use hyper::{body, header, Request, Response, StatusCode};
+use hyper_util::rt::TokioIo;
+use tokio_tungstenite::tungstenite::handshake::derive_accept_key;
+use tokio_tungstenite::tungstenite::protocol::Role;
+use tokio_tungstenite::tungstenite::Message;
+use tokio_tungstenite::WebSocketStream;
+
+use crate::ducking::{self, DuckerController};
+use crate::interstitial;
+use crate::live::LiveInput;
+use crate::server::{self, respond};
+
+/// Largest accepted upload.
+const MAX_UPLOAD_SIZE: usize = 50 * 1024 * 1024;
+const LIVE_SAMPLE_RATE: u32 = 48_000;
+const LIVE_CHANNEL_COUNT: u16 = 2;
+/// Audio collected before a live announcement starts, to ride out jitter.
+const LIVE_PREBUFFER: Duration = Duration::from_millis(200);
+
+/// Announcements should be understood right away, so the music is
+/// lowered quickly and further than for scheduled interstitials.
+const ANNOUNCEMENT_PROFILE: ducking::Profile = ducking::Profile {
+    attack: 0.5,
+    release: 2.0,
+    depth: 0.05,
+    curve: ducking::Curve::Linear,
+    sidechain: false,
+};
+
+/// Plays uploaded or live announcements over the ducked music.
+#[derive(Clone)]
+pub struct Announcer {
+    token: String,
+    mixer_controller: Controller<SoundMixer>,
+    ducker_controller: DuckerController,
+    /// Ducks live sources, announcements get through while a DJ is on air.
+    live_ducker_controller: DuckerController,
+}
+
+impl Announcer {
+    pub fn new(
+        token: String,
+        mixer_controller: Controller<SoundMixer>,
+        ducker_controller: DuckerController,
+        live_ducker_controller: DuckerController,
+    ) -> Self {
+        Self {
+            token,
+            mixer_controller,
+            ducker_controller,
+            live_ducker_controller,
+        }
+    }
+
+    /// Whether the request carries the announcement token.
+    pub fn authorized<B>(&self, req: &Request<B>) -> bool {
+        server::authorized(req, &self.token)
+    }
+
+    /// Play the audio file in the request body right away.
+    pub async fn upload(
+        self,
+        req: Request<body::Incoming>,
+    ) -> anyhow::Result<Response<BoxBody<Bytes, anyhow::Error>>> {
+        let extension = req
+            .headers()
+            .get(header::CONTENT_TYPE)
+            .and_then(|v| v.to_str().ok())
+            .and_then(extension_for);
+        let body = match Limited::new(req.into_body(), MAX_UPLOAD_SIZE)
+            .collect()
+            .await
+        {
+            Ok(body) => body.to_bytes(),
+            Err(e) => return Ok(respond(StatusCode::BAD_REQUEST, e.to_string())),
+        };
+
+        let sound = match awedio::sounds::decoders::SymphoniaDecoder::new(
+            Box::new(symphonia::core::io::ReadOnlySource::new(body.reader())),
+            extension,
+        ) {
+            Ok(sound) => sound,
+            Err(e) => {
+                return Ok(respond(
+                    StatusCode::UNSUPPORTED_MEDIA_TYPE,
+                    format!("Can't decode audio: {e}"),
+                ))
+            }
+        };
+        if sound.channel_count() > 2 {
+            return Ok(respond(
+                StatusCode::UNSUPPORTED_MEDIA_TYPE,
+                "Too many channels",
+            ));
+        }
+
+        tracing::info!("Playing uploaded announcement");
+        self.play(Box::new(sound));
+        Ok(respond(StatusCode::ACCEPTED, "Playing"))
+    }
+
+    /// Accept a WebSocket carrying raw PCM and play it for as long as the
+    /// socket is open.
+    pub fn live(
+        self,
+        mut req: Request<body::Incoming>,
+    ) -> anyhow::Result<Response<BoxBody<Bytes, anyhow::Error>>> {
+        let upgrade = req
+            .headers()
+            .get(header::UPGRADE)
+            .is_some_and(|v| v.as_bytes().eq_ignore_ascii_case(b"websocket"));
+        let Some(key) = req.headers().get(header::SEC_WEBSOCKET_KEY) else {
+            return Ok(respond(StatusCode::BAD_REQUEST, "Expected a WebSocket"));
+        };
+        if !upgrade {
+            return Ok(respond(StatusCode::BAD_REQUEST, "Expected a WebSocket"));
+        }
+        let accept = derive_accept_key(key.as_bytes());
+        let upgraded = hyper::upgrade::on(&mut req);
+
+        tokio::task::spawn(async move {
+            let upgraded = match upgraded.await {
+                Ok(upgraded) => upgraded,
+                Err(e) => {
+                    tracing::error!("Error upgrading live announcement: {}", e);
+                    return;
+                }
+            };
+            let mut socket =
+                WebSocketStream::from_raw_socket(TokioIo::new(upgraded), Role::Server, None).await;
+
+            tracing::info!("Live announcement started");
+            let (sound, sender) =
+                LiveInput::new(LIVE_SAMPLE_RATE, LIVE_CHANNEL_COUNT, LIVE_PREBUFFER);
+            self.play(Box::new(sound));
+            // Messages need not end on a sample, the odd byte starts the next one
+            let mut odd_byte = None;
+            while let Some(message) = socket.next().await {
+                match message {
+                    Ok(Message::Binary(mut data)) => {
+                        if let Some(byte) = odd_byte.take() {
+                            data.insert(0, byte);
+                        }
+                        if data.len() % 2 == 1 {
+                            odd_byte = data.pop();
+                        }
+                        let samples = data
+                            .chunks_exact(2)
+                            .map(|s| i16::from_le_bytes([s[0], s[1]]))
+                            .collect();
+                        if sender.send(samples).is_err() {
+                            break;
+                        }
+                    }
+                    Ok(Message::Close(_)) => break,
+                    Ok(_) => {}
+                    Err(e) => {
+                        tracing::warn!("Live announcement failed: {}", e);
+                        break;
+                    }
+                }
+            }
+            tracing::info!("Live announcement ended");
+        });
+
+        Ok(Response::builder()
+            .status(StatusCode::SWITCHING_PROTOCOLS)
+            .header(header::CONNECTION, "Upgrade")
+            .header(header::UPGRADE, "websocket")
+            .header(header::SEC_WEBSOCKET_ACCEPT, accept)
+            .body(BoxBody::default())
+            .unwrap())
+    }
+
+    fn play(&self, sound: Box<dyn Sound>) {
+        let mut mixer_controller = self.mixer_controller.clone();
+        let mut ducker_controller = self.ducker_controller.clone();
+        let mut live_ducker_controller = self.live_ducker_controller.clone();
+        tokio::task::spawn(async move {
+            // A live source on air is lowered like the music
+            let (live_hold, ()) = tokio::join!(
+                live_ducker_controller.duck(ANNOUNCEMENT_PROFILE, None),
+                interstitial::play_ducked(
+                    sound,
+                    ANNOUNCEMENT_PROFILE,
+                    &mut mixer_controller,
+                    &mut ducker_controller,
+                )
+            );
+            live_ducker_controller.release(live_hold);
+        });
+    }
+}
+
+fn extension_for(content_type: &str) -> Option<&'static str> {
+    let extension = match content_type.split(';').next()?.trim() {
+        "audio/mpeg" | "audio/mp3" => "mp3",
+        "audio/wav" | "audio/x-wav" | "audio/wave" => "wav",
+        "audio/ogg" => "ogg",
+        "audio/flac" | "audio/x-flac" => "flac",
+        "audio/aac" => "aac",
+        "audio/mp4" | "audio/x-m4a" => "m4a",
+        _ => return None,
+    };
+    Some(extension)
+}
@@ -0,0 +1,462 @@
+use std::sync::atomic::{AtomicU32, AtomicU64, Ordering};
+use std::sync::Arc;
+
+use awedio::sounds::wrappers::{Controllable, Controller};
+use awedio::{NextSample, Sound};
+use serde::Deserialize;
+use tokio::sync::oneshot;
+
+/// Voice level at which sidechain ducking reaches the full depth.
+const SIDECHAIN_THRESHOLD: f32 = 0.1;
+/// Per sample decay of the sidechain level follower.
+const SIDECHAIN_DECAY: f32 = 0.9995;
+
+/// How the music is lowered while an interstitial plays.
+#[derive(Debug, Clone, Copy, Deserialize)]
+#[serde(default)]
+pub struct Profile {
+    /// Seconds to fade the music down.
+    pub attack: f32,
+    /// Seconds to fade the music back up.
+    pub release: f32,
+    /// Level of the music while ducked, 0 is silent and 1 not ducked at all.
+    pub depth: f32,
+    pub curve: Curve,
+    /// Follow the level of the interstitial instead of ducking for its whole
+    /// length, so the music comes up again in pauses.
+    pub sidechain: bool,
+}
+
+impl Default for Profile {
+    fn default() -> Self {
+        Self {
+            attack: 2.0,
+            release: 2.0,
+            depth: 0.1,
+            curve: Curve::Linear,
+            sidechain: false,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Curve {
+    Linear,
+    /// Linear in decibels, which sounds more even than a linear fade.
+    Exponential,
+    /// Starts and ends slowly.
+    #[serde(rename = "s-curve")]
+    Smooth,
+}
+
+impl Profile {
+    fn clamped_depth(&self) -> f32 {
+        self.depth.clamp(0.0, 1.0)
+    }
+
+    /// Gain for the given progress of the fade, from 0 (not ducked) to 1.
+    fn gain(&self, progress: f32) -> f32 {
+        let depth = self.clamped_depth();
+        match self.curve {
+            Curve::Linear => 1.0 - (1.0 - depth) * progress,
+            Curve::Exponential => {
+                // Silence has no decibel value, stop at -60 dB
+                depth.max(0.001).powf(progress)
+            }
+            Curve::Smooth => {
+                let smooth = progress * progress * (3.0 - 2.0 * progress);
+                1.0 - (1.0 - depth) * smooth
+            }
+        }
+    }
+
+    /// Progress of the fade at which the music has the given gain, the
+    /// inverse of `gain`.
+    fn progress_for(&self, gain: f32) -> f32 {
+        let depth = self.clamped_depth();
+        if depth >= 1.0 {
+            return 0.0;
+        }
+        let linear = ((1.0 - gain) / (1.0 - depth)).clamp(0.0, 1.0);
+        let progress = match self.curve {
+            Curve::Linear => linear,
+            Curve::Exponential => gain.max(0.001).ln() / depth.max(0.001).ln(),
+            Curve::Smooth => 0.5 - ((1.0 - 2.0 * linear).asin() / 3.0).sin(),
+        };
+        progress.clamp(0.0, 1.0)
+    }
+}
+
+/// Something ducking the music, like an interstitial or a live source.
+struct Hold {
+    id: u64,
+    profile: Profile,
+    sidechain: Option<Arc<AtomicU32>>,
+    /// Told once the music is down to the depth of this hold.
+    on_ducked: Option<oneshot::Sender<()>>,
+}
+
+/// Lowers the volume of the wrapped sound, with fades computed per sample.
+pub struct Ducker {
+    inner: Box<dyn Sound>,
+    /// Profile the fades follow, the deepest of all holds. Kept after the
+    /// last hold is released, to fade back up with it.
+    profile: Profile,
+    /// 0 is not ducked, 1 fully ducked to the depth of `profile`.
+    progress: f32,
+    /// Samples per second over all channels of the inner sound.
+    sample_rate: f32,
+    holds: Vec<Hold>,
+}
+
+impl Ducker {
+    pub fn new(inner: Box<dyn Sound>) -> (Controllable<Ducker>, DuckerController) {
+        let (controllable, controller) = Self::wrap(inner).controllable();
+        (
+            controllable,
+            DuckerController {
+                controller,
+                next_hold: Default::default(),
+            },
+        )
+    }
+
+    fn wrap(inner: Box<dyn Sound>) -> Self {
+        let sample_rate = (inner.sample_rate() * inner.channel_count() as u32) as f32;
+        Ducker {
+            inner,
+            profile: Profile::default(),
+            progress: 0.0,
+            sample_rate,
+            holds: Vec::new(),
+        }
+    }
+
+    fn duck(
+        &mut self,
+        id: u64,
+        profile: Profile,
+        sidechain: Option<Arc<AtomicU32>>,
+        on_ducked: oneshot::Sender<()>,
+    ) {
+        let on_ducked = if sidechain.is_some() {
+            // Sidechained interstitials start right away, the music follows their level
+            let _ = on_ducked.send(());
+            None
+        } else {
+            Some(on_ducked)
+        };
+        self.holds.push(Hold {
+            id,
+            profile,
+            sidechain,
+            on_ducked,
+        });
+        self.update_profile();
+        self.notify_ducked();
+    }
+
+    fn release(&mut self, id: u64) {
+        self.holds.retain(|hold| hold.id != id);
+        self.update_profile();
+    }
+
+    /// Follow the deepest hold, fading back up as slowly as the slowest.
+    fn update_profile(&mut self) {
+        let Some(deepest) = self.holds.iter().min_by(|a, b| {
+            a.profile
+                .clamped_depth()
+                .total_cmp(&b.profile.clamped_depth())
+        }) else {
+            return;
+        };
+        let mut profile = deepest.profile;
+        profile.release = self
+            .holds
+            .iter()
+            .map(|hold| hold.profile.release)
+            .fold(profile.release, f32::max);
+        // Carry on from the current level, whatever the new curve
+        let gain = self.profile.gain(self.progress);
+        self.progress = profile.progress_for(gain);
+        self.profile = profile;
+    }
+
+    /// Progress the hold asks for on the current profile.
+    fn hold_target(&self, hold: &Hold) -> f32 {
+        let full = self.profile.progress_for(hold.profile.clamped_depth());
+        match &hold.sidechain {
+            Some(level) => {
+                let level = f32::from_bits(level.load(Ordering::Relaxed));
+                full * (level / SIDECHAIN_THRESHOLD).min(1.0)
+            }
+            None => full,
+        }
+    }
+
+    fn target(&self) -> f32 {
+        self.holds
+            .iter()
+            .map(|hold| self.hold_target(hold))
+            .fold(0.0, f32::max)
+    }
+
+    /// Tell the holds waiting for the music to reach their depth.
+    fn notify_ducked(&mut self) {
+        let reached: Vec<bool> = self
+            .holds
+            .iter()
+            .map(|hold| hold.on_ducked.is_some() && self.progress >= self.hold_target(hold) - 1e-4)
+            .collect();
+        for (hold, reached) in self.holds.iter_mut().zip(reached) {
+            if reached {
+                if let Some(sender) = hold.on_ducked.take() {
+                    let _ = sender.send(());
+                }
+            }
+        }
+    }
+
+    fn advance(&mut self) {
+        let target = self.target();
+        if self.progress < target {
+            let step = 1.0 / (self.profile.attack.max(0.0) * self.sample_rate).max(1.0);
+            self.progress = (self.progress + step).min(target);
+            if self.holds.iter().any(|hold| hold.on_ducked.is_some()) {
+                self.notify_ducked();
+            }
+        } else if self.progress > target {
+            let step = 1.0 / (self.profile.release.max(0.0) * self.sample_rate).max(1.0);
+            self.progress = (self.progress - step).max(target);
+        }
+    }
+}
+
+impl Sound for Ducker {
+    fn channel_count(&self) -> u16 {
+        self.inner.channel_count()
+    }
+
+    fn sample_rate(&self) -> u32 {
+        self.inner.sample_rate()
+    }
+
+    fn next_sample(&mut self) -> Result<NextSample, awedio::Error> {
+        let next = self.inner.next_sample()?;
+        match next {
+            NextSample::Sample(s) => {
+                self.advance();
+                Ok(NextSample::Sample(
+                    (s as f32 * self.profile.gain(self.progress)) as i16,
+                ))
+            }
+            NextSample::MetadataChanged => {
+                self.sample_rate =
+                    (self.inner.sample_rate() * self.inner.channel_count() as u32) as f32;
+                Ok(next)
+            }
+            NextSample::Paused => {
+                // Keep the fades going while no music plays
+                self.advance();
+                Ok(next)
+            }
+            NextSample::Finished => Ok(next),
+        }
+    }
+
+    fn on_start_of_batch(&mut self) {
+        self.inner.on_start_of_batch();
+    }
+}
+
+#[derive(Clone)]
+pub struct DuckerController {
+    controller: Controller<Ducker>,
+    next_hold: Arc<AtomicU64>,
+}
+
+/// Identifies a `duck` call, to release it again.
+#[derive(Debug, Clone, Copy)]
+pub struct HoldId(u64);
+
+impl DuckerController {
+    /// Start ducking. Returns once the music is down to the depth of
+    /// `profile`, or right away for sidechain ducking, which follows the
+    /// level of `sidechain`. While several holds are active the deepest
+    /// one wins.
+    pub async fn duck(&mut self, profile: Profile, sidechain: Option<Arc<AtomicU32>>) -> HoldId {
+        let id = self.next_hold.fetch_add(1, Ordering::Relaxed);
+        let (sender, receiver) = oneshot::channel();
+        self.controller
+            .send_command(Box::new(move |d: &mut Ducker| {
+                d.duck(id, profile, sidechain, sender)
+            }));
+        let _ = receiver.await;
+        HoldId(id)
+    }
+
+    /// End the hold, fading the music back up once nothing else ducks it.
+    pub fn release(&mut self, hold: HoldId) {
+        self.controller
+            .send_command(Box::new(move |d: &mut Ducker| d.release(hold.0)));
+    }
+}
+
+/// Measures the level of the wrapped sound for sidechain ducking.
+pub struct Sidechain {
+    inner: Box<dyn Sound>,
+    level: f32,
+    shared_level: Arc<AtomicU32>,
+}
+
+impl Sidechain {
+    pub fn new(inner: Box<dyn Sound>) -> (Self, Arc<AtomicU32>) {
+        let shared_level = Arc::new(AtomicU32::new(0.0_f32.to_bits()));
+        (
+            Self {
+                inner,
+                level: 0.0,
+                shared_level: shared_level.clone(),
+            },
+            shared_level,
+        )
+    }
+}
+
+impl Sound for Sidechain {
+    fn channel_count(&self) -> u16 {
+        self.inner.channel_count()
+    }
+
+    fn sample_rate(&self) -> u32 {
+        self.inner.sample_rate()
+    }
+
+    fn next_sample(&mut self) -> Result<NextSample, awedio::Error> {
+        let next = self.inner.next_sample();
+        match &next {
+            Ok(NextSample::Sample(s)) => {
+                let amplitude = (*s as f32 / i16::MAX as f32).abs();
+                self.level = amplitude.max(self.level * SIDECHAIN_DECAY);
+            }
+            Ok(NextSample::MetadataChanged | NextSample::Paused) => {}
+            Ok(NextSample::Finished) | Err(_) => self.level = 0.0,
+        }
+        self.shared_level
+            .store(self.level.to_bits(), Ordering::Relaxed);
+        next
+    }
+
+    fn on_start_of_batch(&mut self) {
+        self.inner.on_start_of_batch();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Plays full scale forever.
+    struct Constant;
+
+    impl Sound for Constant {
+        fn channel_count(&self) -> u16 {
+            1
+        }
+
+        fn sample_rate(&self) -> u32 {
+            1000
+        }
+
+        fn next_sample(&mut self) -> Result<NextSample, awedio::Error> {
+            Ok(NextSample::Sample(i16::MAX))
+        }
+
+        fn on_start_of_batch(&mut self) {}
+    }
+
+    fn profile(depth: f32, curve: Curve) -> Profile {
+        Profile {
+            attack: 0.1,
+            release: 0.1,
+            depth,
+            curve,
+            sidechain: false,
+        }
+    }
+
+    /// Let `seconds` of samples through, returning the last gain.
+    fn play(ducker: &mut Ducker, seconds: f32) -> f32 {
+        for _ in 0..(seconds * ducker.sample_rate) as usize {
+            ducker.next_sample().unwrap();
+        }
+        ducker.profile.gain(ducker.progress)
+    }
+
+    #[test]
+    fn progress_for_inverts_gain() {
+        for curve in [Curve::Linear, Curve::Exponential, Curve::Smooth] {
+            let profile = profile(0.2, curve);
+            for step in 0..=10 {
+                let progress = step as f32 / 10.0;
+                let back = profile.progress_for(profile.gain(progress));
+                assert!((back - progress).abs() < 1e-3, "{curve:?} at {progress}");
+            }
+        }
+    }
+
+    #[test]
+    fn deepest_hold_wins() {
+        let mut ducker = Ducker::wrap(Box::new(Constant));
+        let (takeover, _) = oneshot::channel();
+        ducker.duck(0, profile(0.0, Curve::Smooth), None, takeover);
+        assert_eq!(play(&mut ducker, 0.2), 0.0);
+
+        let (announcement, _) = oneshot::channel();
+        ducker.duck(1, profile(0.05, Curve::Linear), None, announcement);
+        assert_eq!(play(&mut ducker, 0.2), 0.0);
+        ducker.release(1);
+        assert_eq!(play(&mut ducker, 0.2), 0.0);
+
+        ducker.release(0);
+        assert_eq!(play(&mut ducker, 0.2), 1.0);
+    }
+
+    #[test]
+    fn slowest_release_wins() {
+        let mut ducker = Ducker::wrap(Box::new(Constant));
+        let (first, _) = oneshot::channel();
+        ducker.duck(0, profile(0.5, Curve::Linear), None, first);
+        let (second, _) = oneshot::channel();
+        let slow = Profile {
+            release: 1.0,
+            ..profile(0.8, Curve::Linear)
+        };
+        ducker.duck(1, slow, None, second);
+        play(&mut ducker, 0.2);
+        ducker.release(0);
+        ducker.release(1);
+        assert!(play(&mut ducker, 0.2) < 1.0);
+        assert_eq!(play(&mut ducker, 1.0), 1.0);
+    }
+
+    #[test]
+    fn waiter_resolves_during_sidechain() {
+        let mut ducker = Ducker::wrap(Box::new(Constant));
+        let (sidechained, mut sidechained_ducked) = oneshot::channel();
+        let level = Arc::new(AtomicU32::new(0.0f32.to_bits()));
+        let sidechain = Profile {
+            sidechain: true,
+            ..profile(0.1, Curve::Linear)
+        };
+        ducker.duck(0, sidechain, Some(level), sidechained);
+        assert!(sidechained_ducked.try_recv().is_ok());
+
+        let (waiter, mut ducked) = oneshot::channel();
+        ducker.duck(1, profile(0.3, Curve::Linear), None, waiter);
+        assert!(ducked.try_recv().is_err());
+        play(&mut ducker, 0.2);
+        assert!(ducked.try_recv().is_ok());
+    }
+}
@@ -0,0 +1,197 @@
+use std::fmt::Display;
+
+use bytes::Bytes;
+use futures_util::{SinkExt, StreamExt};
+use http_body_util::combinators::BoxBody;
+use hyper::{body, header, Request, Response, StatusCode};
+use hyper_util::rt::TokioIo;
+use serde::Serialize;
+use tokio::sync::broadcast::{self, error::RecvError};
+use tokio_stream::wrappers::BroadcastStream;
+use tokio_tungstenite::tungstenite::handshake::derive_accept_key;
+use tokio_tungstenite::tungstenite::protocol::Role;
+use tokio_tungstenite::tungstenite::Message;
+use tokio_tungstenite::WebSocketStream;
+
+use crate::nowplaying::Song;
+use crate::player::{PlayerEvent, Track};
+use crate::server::{event_stream, respond};
+
+/// Events kept for subscribers which fall behind.
+const CAPACITY: usize = 64;
+
+/// Something happening on the station, as published on `/events`.
+#[derive(Serialize, Clone)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Event {
+    TrackStarted {
+        track: Song,
+    },
+    /// The track could not be played to its end.
+    TrackSkipped {
+        track: Song,
+        /// Seconds played before the track was dropped.
+        position: f64,
+    },
+    InterstitialStarted {
+        name: String,
+    },
+    InterstitialEnded {
+        name: String,
+    },
+    ListenerConnected {
+        id: u64,
+        address: Option<String>,
+    },
+    ListenerDisconnected {
+        id: u64,
+        address: Option<String>,
+        /// Seconds the listener was connected.
+        duration: f64,
+    },
+    /// Audio could not be taken from a source, like Jellyfin or a live
+    /// source client.
+    SourceError {
+        source: String,
+        message: String,
+    },
+}
+
+/// Distributes events to the feed subscribers.
+#[derive(Clone)]
+pub struct EventBus(broadcast::Sender<Event>);
+
+impl Default for EventBus {
+    fn default() -> Self {
+        Self(broadcast::channel(CAPACITY).0)
+    }
+}
+
+impl EventBus {
+    pub fn publish(&self, event: Event) {
+        // Nobody listening is fine
+        let _ = self.0.send(event);
+    }
+
+    pub fn source_error(&self, source: &str, error: impl Display) {
+        self.publish(Event::SourceError {
+            source: source.to_owned(),
+            message: error.to_string(),
+        });
+    }
+
+    pub fn subscribe(&self) -> broadcast::Receiver<Event> {
+        self.0.subscribe()
+    }
+}
+
+/// Publish what the player does. Interstitials played over the music don't
+/// pass the player, their events are published where they are played.
+pub async fn forward_player_events(bus: EventBus, mut events: broadcast::Receiver<PlayerEvent>) {
+    loop {
+        let event = match events.recv().await {
+            Ok(PlayerEvent::Started(Track::Song(audio))) => Event::TrackStarted {
+                track: Song::from(&audio),
+            },
+            Ok(PlayerEvent::Skipped {
+                track: Track::Song(audio),
+                position,
+            }) => Event::TrackSkipped {
+                track: Song::from(&audio),
+                position: position.as_secs_f64(),
+            },
+            Ok(PlayerEvent::Started(Track::Interstitial(name))) => {
+                Event::InterstitialStarted { name }
+            }
+            Ok(
+                PlayerEvent::Finished {
+                    track: Track::Interstitial(name),
+                    ..
+                }
+                | PlayerEvent::Skipped {
+                    track: Track::Interstitial(name),
+                    ..
+                },
+            ) => Event::InterstitialEnded { name },
+            Ok(
+                PlayerEvent::Finished { .. }
+                | PlayerEvent::Paused { .. }
+                | PlayerEvent::Resumed { .. },
+            ) => continue,
+            Err(RecvError::Lagged(skipped)) => {
+                tracing::warn!("Event feed missed {} player events", skipped);
+                continue;
+            }
+            Err(RecvError::Closed) => return,
+        };
+        bus.publish(event);
+    }
+}
+
+/// The feed over Server-Sent Events, or over a WebSocket if the request
+/// asks for an upgrade.
+pub fn feed(
+    bus: EventBus,
+    mut req: Request<body::Incoming>,
+) -> anyhow::Result<Response<BoxBody<Bytes, anyhow::Error>>> {
+    let upgrade = req
+        .headers()
+        .get(header::UPGRADE)
+        .is_some_and(|v| v.as_bytes().eq_ignore_ascii_case(b"websocket"));
+    if !upgrade {
+        let events = BroadcastStream::new(bus.subscribe()).filter_map(|event| async move {
+            // Subscribers which fall behind just miss some events
+            let json = serde_json::to_string(&event.ok()?).ok()?;
+            Some(format!("data: {json}\n\n"))
+        });
+        return Ok(event_stream(events));
+    }
+
+    let Some(key) = req.headers().get(header::SEC_WEBSOCKET_KEY) else {
+        return Ok(respond(StatusCode::BAD_REQUEST, "Expected a WebSocket"));
+    };
+    let accept = derive_accept_key(key.as_bytes());
+    let upgraded = hyper::upgrade::on(&mut req);
+    let mut events = bus.subscribe();
+
+    tokio::task::spawn(async move {
+        let upgraded = match upgraded.await {
+            Ok(upgraded) => upgraded,
+            Err(e) => {
+                tracing::error!("Error upgrading event feed: {}", e);
+                return;
+            }
+        };
+        let mut socket =
+            WebSocketStream::from_raw_socket(TokioIo::new(upgraded), Role::Server, None).await;
+        loop {
+            tokio::select! {
+                event = events.recv() => {
+                    let event = match event {
+                        Ok(event) => event,
+                        Err(RecvError::Lagged(_)) => continue,
+                        Err(RecvError::Closed) => break,
+                    };
+                    let json = serde_json::to_string(&event).unwrap_or_default();
+                    if socket.send(Message::Text(json)).await.is_err() {
+                        break;
+                    }
+                }
+                // Only read to notice the client leaving, pings are answered
+                // by the socket itself
+                message = socket.next() => match message {
+                    Some(Ok(Message::Close(_))) | Some(Err(_)) | None => break,
+                    Some(Ok(_)) => {}
+                },
+            }
+        }
+    });
+
+    Ok(Response::builder()
+        .status(StatusCode::SWITCHING_PROTOCOLS)
+        .header(header::CONNECTION, "Upgrade")
+        .header(header::UPGRADE, "websocket")
+        .header(header::SEC_WEBSOCKET_ACCEPT, accept)
+        .body(BoxBody::default())
+        .unwrap())
+}
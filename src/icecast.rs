@@ -0,0 +1,158 @@
+use std::time::Duration;
+
+use base64::Engine;
+use futures_util::StreamExt;
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+use tokio::net::TcpStream;
+use tokio::sync::broadcast::{self, error::RecvError};
+
+use crate::player::{PlayerEvent, Track};
+use crate::streamer::{self, Mp3Encoder, StreamerBackend};
+
+const INITIAL_BACKOFF: Duration = Duration::from_secs(1);
+const MAX_BACKOFF: Duration = Duration::from_secs(60);
+/// A connection which lasted this long resets the backoff.
+const STABLE_CONNECTION: Duration = Duration::from_secs(60);
+const RESPONSE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// An Icecast2 mount to push the station to as a source client.
+#[derive(Clone)]
+pub struct Output {
+    /// `http://host:port/mount`.
+    pub url: reqwest::Url,
+    pub user: String,
+    pub password: String,
+    pub name: String,
+    pub description: Option<String>,
+    pub genre: Option<String>,
+    pub public: bool,
+}
+
+impl Output {
+    fn authorization(&self) -> String {
+        let credentials = format!("{}:{}", self.user, self.password);
+        format!(
+            "Basic {}",
+            base64::engine::general_purpose::STANDARD.encode(credentials)
+        )
+    }
+
+    /// Connect, then push the encoded stream until the connection fails.
+    async fn push(&self, streamer: &StreamerBackend) -> anyhow::Result<()> {
+        let host = self
+            .url
+            .host_str()
+            .ok_or(anyhow::anyhow!("No host in {}", self.url))?;
+        let port = self.url.port_or_known_default().unwrap_or(8000);
+        let mut tcp = TcpStream::connect((host, port)).await?;
+
+        let mut request = format!(
+            "PUT {} HTTP/1.1\r\n\
+             Host: {host}:{port}\r\n\
+             Authorization: {}\r\n\
+             User-Agent: jellyfin-radio/{}\r\n\
+             Content-Type: audio/mpeg\r\n\
+             Expect: 100-continue\r\n\
+             ice-name: {}\r\n\
+             ice-public: {}\r\n\
+             ice-audio-info: channels={};samplerate={};bitrate={}\r\n",
+            self.url.path(),
+            self.authorization(),
+            env!("CARGO_PKG_VERSION"),
+            self.name,
+            self.public as u8,
+            streamer::CHANNEL_COUNT,
+            streamer::SAMPLE_RATE,
+            streamer::BITRATE,
+        );
+        if let Some(description) = &self.description {
+            request.push_str(&format!("ice-description: {description}\r\n"));
+        }
+        if let Some(genre) = &self.genre {
+            request.push_str(&format!("ice-genre: {genre}\r\n"));
+        }
+        request.push_str("\r\n");
+        tcp.write_all(request.as_bytes()).await?;
+
+        let mut status_line = String::new();
+        let mut reader = BufReader::new(&mut tcp);
+        tokio::time::timeout(RESPONSE_TIMEOUT, reader.read_line(&mut status_line)).await??;
+        let status = status_line.split_whitespace().nth(1).unwrap_or_default();
+        if status != "100" && status != "200" {
+            anyhow::bail!("Icecast answered {:?}", status_line.trim());
+        }
+        // Skip the rest of the response head
+        loop {
+            let mut line = String::new();
+            tokio::time::timeout(RESPONSE_TIMEOUT, reader.read_line(&mut line)).await??;
+            if line.trim().is_empty() {
+                break;
+            }
+        }
+        tracing::info!("Pushing to Icecast at {}", self.url);
+
+        let mut encoder = Mp3Encoder::new();
+        let mut pcm = streamer.subscribe();
+        while let Some(data) = pcm.next().await {
+            tcp.write_all(&encoder.encode(&data)).await?;
+        }
+        Ok(())
+    }
+
+    /// Set the title shown by Icecast for the mount.
+    async fn update_metadata(&self, client: &reqwest::Client, song: &str) -> anyhow::Result<()> {
+        let mut url = self.url.clone();
+        url.set_path("/admin/metadata");
+        client
+            .get(url)
+            .query(&[
+                ("mount", self.url.path()),
+                ("mode", "updinfo"),
+                ("song", song),
+            ])
+            .basic_auth(&self.user, Some(&self.password))
+            .send()
+            .await?
+            .error_for_status()?;
+        Ok(())
+    }
+}
+
+/// Push the station to an Icecast server, reconnecting with backoff, and
+/// keep the metadata of the mount up to date.
+pub async fn run(
+    output: Output,
+    streamer: StreamerBackend,
+    events: broadcast::Receiver<PlayerEvent>,
+) {
+    tokio::task::spawn(metadata(output.clone(), events));
+
+    let mut backoff = INITIAL_BACKOFF;
+    loop {
+        let connected_at = tokio::time::Instant::now();
+        if let Err(e) = output.push(&streamer).await {
+            tracing::warn!("Icecast connection to {} failed: {}", output.url, e);
+        }
+        if connected_at.elapsed() >= STABLE_CONNECTION {
+            backoff = INITIAL_BACKOFF;
+        }
+        tracing::info!("Reconnecting to Icecast in {:?}", backoff);
+        tokio::time::sleep(backoff).await;
+        backoff = (backoff * 2).min(MAX_BACKOFF);
+    }
+}
+
+async fn metadata(output: Output, mut events: broadcast::Receiver<PlayerEvent>) {
+    let client = reqwest::Client::new();
+    loop {
+        let audio = match events.recv().await {
+            Ok(PlayerEvent::Started(Track::Song(audio))) => audio,
+            Ok(_) | Err(RecvError::Lagged(_)) => continue,
+            Err(RecvError::Closed) => return,
+        };
+        let song = format!("{} - {}", audio.artists.join(", "), audio.name);
+        if let Err(e) = output.update_metadata(&client, &song).await {
+            tracing::warn!("Error updating Icecast metadata: {}", e);
+        }
+    }
+}
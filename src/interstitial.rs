@@ -0,0 +1,632 @@
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+use awedio::sounds::wrappers::Controller;
+use awedio::sounds::SoundMixer;
+use awedio::Sound;
+use chrono::{DateTime, Datelike, Local};
+use rand::seq::SliceRandom;
+use serde::Deserialize;
+use tokio::sync::broadcast::error::RecvError;
+
+use crate::ducking::{self, DuckerController};
+use crate::events::{Event, EventBus};
+use crate::player::{PlayerController, PlayerEvent, Track};
+
+const MANIFEST_FILE: &str = "schedule.toml";
+pub const TIME_FOLDER: &str = "time";
+/// Ducking profile used for entries without one.
+const DEFAULT_PROFILE: &str = "default";
+/// Ducking profile used for the files in the time folder, if it exists.
+const TIME_PROFILE: &str = "time";
+/// How often the folder is checked for changes.
+const RELOAD_INTERVAL: Duration = Duration::from_secs(10);
+/// How far ahead the next interstitial is searched for.
+const SEARCH_DAYS: u64 = 366;
+/// Cron times looked at before giving up on finding the next interstitial,
+/// so a schedule which never plays can't keep the search busy.
+const SEARCH_STEPS: usize = 1000;
+
+/// When interstitials are played.
+///
+/// Built from the `time/HH_MM.*` files, which play every day at the given
+/// time, and the optional `schedule.toml` manifest in the interstitial folder.
+pub struct Schedule {
+    entries: Vec<Entry>,
+}
+
+/// An interstitial which is due.
+pub struct Due {
+    pub time: DateTime<Local>,
+    pub path: PathBuf,
+    pub ducking: ducking::Profile,
+}
+
+struct Entry {
+    /// A file, or a folder to pick a random file from.
+    path: PathBuf,
+    cron: cron::Schedule,
+    weekdays: Vec<chrono::Weekday>,
+    dates: Option<(MonthDay, MonthDay)>,
+    weight: u32,
+    ducking: ducking::Profile,
+}
+
+type MonthDay = (u32, u32);
+
+#[derive(Deserialize)]
+struct Manifest {
+    #[serde(default, rename = "interstitial")]
+    interstitials: Vec<ManifestEntry>,
+    #[serde(default)]
+    ducking: HashMap<String, ducking::Profile>,
+}
+
+#[derive(Deserialize)]
+struct ManifestEntry {
+    file: PathBuf,
+    cron: String,
+    #[serde(default)]
+    weekdays: Vec<String>,
+    from: Option<String>,
+    until: Option<String>,
+    #[serde(default = "default_weight")]
+    weight: u32,
+    ducking: Option<String>,
+}
+
+fn default_weight() -> u32 {
+    1
+}
+
+fn parse_month_day(value: &str) -> anyhow::Result<MonthDay> {
+    let (month, day) = value
+        .split_once('-')
+        .ok_or(anyhow::anyhow!("Expected MM-DD, got {value:?}"))?;
+    let month_day = (month.parse()?, day.parse()?);
+    // Leap year, so the 29th of February is valid
+    chrono::NaiveDate::from_ymd_opt(2024, month_day.0, month_day.1)
+        .ok_or(anyhow::anyhow!("Invalid date {value:?}"))?;
+    Ok(month_day)
+}
+
+impl Entry {
+    fn from_manifest(
+        folder: &Path,
+        entry: ManifestEntry,
+        profiles: &HashMap<String, ducking::Profile>,
+    ) -> anyhow::Result<Self> {
+        let cron = entry
+            .cron
+            .parse()
+            .map_err(|e| anyhow::anyhow!("Invalid cron expression {:?}: {}", entry.cron, e))?;
+        let weekdays = entry
+            .weekdays
+            .iter()
+            .map(|d| {
+                d.parse()
+                    .map_err(|_| anyhow::anyhow!("Invalid weekday {d:?}"))
+            })
+            .collect::<anyhow::Result<_>>()?;
+        let dates = match (entry.from, entry.until) {
+            (None, None) => None,
+            (from, until) => Some((
+                parse_month_day(from.as_deref().unwrap_or("01-01"))?,
+                parse_month_day(until.as_deref().unwrap_or("12-31"))?,
+            )),
+        };
+        let ducking = match entry.ducking {
+            Some(name) => *profiles
+                .get(&name)
+                .ok_or(anyhow::anyhow!("Unknown ducking profile {name:?}"))?,
+            None => profiles.get(DEFAULT_PROFILE).copied().unwrap_or_default(),
+        };
+        Ok(Self {
+            path: folder.join(entry.file),
+            cron,
+            weekdays,
+            dates,
+            weight: entry.weight,
+            ducking,
+        })
+    }
+
+    /// Parse a `HH_MM.*` file from the time folder.
+    fn from_time_file(path: &Path, ducking: ducking::Profile) -> anyhow::Result<Self> {
+        let mut name_split = path
+            .file_stem()
+            .ok_or(anyhow::anyhow!("Wrong file stem!"))?
+            .to_str()
+            .ok_or(anyhow::anyhow!("Wrong file path!"))?
+            .split('_');
+        let hour: u32 = name_split
+            .next()
+            .ok_or(anyhow::anyhow!("No hour!"))?
+            .parse()?;
+        let minute: u32 = name_split
+            .next()
+            .ok_or(anyhow::anyhow!("No minute!"))?
+            .parse()?;
+        chrono::NaiveTime::from_hms_opt(hour, minute, 0)
+            .ok_or(anyhow::anyhow!("Can't parse time"))?;
+        Ok(Self {
+            path: path.to_owned(),
+            cron: format!("0 {minute} {hour} * * *").parse()?,
+            weekdays: Vec::new(),
+            dates: None,
+            weight: 1,
+            ducking,
+        })
+    }
+
+    fn matches(&self, time: &DateTime<Local>) -> bool {
+        if !self.weekdays.is_empty() && !self.weekdays.contains(&time.weekday()) {
+            return false;
+        }
+        match self.dates {
+            None => true,
+            Some((from, until)) => {
+                let date = (time.month(), time.day());
+                if from <= until {
+                    from <= date && date <= until
+                } else {
+                    // Wraps around the new year
+                    date >= from || date <= until
+                }
+            }
+        }
+    }
+
+    fn next(&self, after: &DateTime<Local>) -> Option<DateTime<Local>> {
+        let limit = after.checked_add_days(chrono::Days::new(SEARCH_DAYS))?;
+        let mut after = *after;
+        for _ in 0..SEARCH_STEPS {
+            let time = self.cron.after(&after).next()?;
+            if time > limit {
+                return None;
+            }
+            if self.matches(&time) {
+                return Some(time);
+            }
+            // Weekdays and dates hold for whole days, skip the rest of this one
+            after = end_of_day(&time);
+        }
+        None
+    }
+
+    fn pick_file(&self) -> anyhow::Result<PathBuf> {
+        if !self.path.is_dir() {
+            return Ok(self.path.clone());
+        }
+        let files: Vec<PathBuf> = std::fs::read_dir(&self.path)?
+            .filter_map(|v| v.ok())
+            .map(|v| v.path())
+            .filter(|v| !v.is_dir())
+            .collect();
+        files
+            .choose(&mut rand::thread_rng())
+            .cloned()
+            .ok_or(anyhow::anyhow!("No files in {:?}", self.path))
+    }
+}
+
+impl Schedule {
+    /// Load the schedule from the folder. Problems are logged and the
+    /// affected files skipped, so a broken file never stops the station.
+    pub fn load(folder: &Path) -> Self {
+        let mut entries = Vec::new();
+
+        let manifest_path = folder.join(MANIFEST_FILE);
+        let manifest = if manifest_path.exists() {
+            tracing::info!("Reading interstitial schedule {:?}", manifest_path);
+            let manifest = std::fs::read_to_string(&manifest_path)
+                .map_err(anyhow::Error::from)
+                .and_then(|manifest| Ok(toml::from_str::<Manifest>(&manifest)?));
+            match manifest {
+                Ok(manifest) => Some(manifest),
+                Err(e) => {
+                    tracing::warn!("Ignoring schedule {:?}: {}", manifest_path, e);
+                    None
+                }
+            }
+        } else {
+            None
+        };
+        let (interstitials, profiles) = manifest
+            .map(|m| (m.interstitials, m.ducking))
+            .unwrap_or_default();
+
+        let time_profile = profiles
+            .get(TIME_PROFILE)
+            .or(profiles.get(DEFAULT_PROFILE))
+            .copied()
+            .unwrap_or_default();
+        let time_folder = folder.join(TIME_FOLDER);
+        match std::fs::read_dir(&time_folder) {
+            Ok(files) => {
+                tracing::info!("Looking for time files at {:?}", time_folder);
+                for file in files.filter_map(|v| v.ok()).filter(|v| !v.path().is_dir()) {
+                    match Entry::from_time_file(&file.path(), time_profile) {
+                        Ok(entry) => entries.push(entry),
+                        Err(e) => tracing::warn!("Ignoring time file {:?}: {}", file.path(), e),
+                    }
+                }
+            }
+            Err(e) => tracing::warn!("Can't read time files at {:?}: {}", time_folder, e),
+        }
+
+        for entry in interstitials {
+            let file = entry.file.clone();
+            match Entry::from_manifest(folder, entry, &profiles) {
+                Ok(entry) => entries.push(entry),
+                Err(e) => tracing::warn!("Ignoring schedule entry for {:?}: {}", file, e),
+            }
+        }
+
+        Self { entries }
+    }
+
+    /// Load the schedules of several folders into one.
+    pub fn load_all(folders: &[PathBuf]) -> Self {
+        let entries = folders
+            .iter()
+            .flat_map(|folder| Self::load(folder).entries)
+            .collect();
+        Self { entries }
+    }
+
+    /// The next interstitial after `now`. If several are scheduled at the same
+    /// time, one of them is picked according to their weights.
+    pub fn next(&self, now: &DateTime<Local>) -> Option<Due> {
+        let mut after = *now;
+        for _ in 0..SEARCH_STEPS {
+            let upcoming: Vec<(DateTime<Local>, &Entry)> = self
+                .entries
+                .iter()
+                .filter_map(|entry| entry.next(&after).map(|time| (time, entry)))
+                .collect();
+            let time = upcoming.iter().map(|(time, _)| *time).min()?;
+            let candidates: Vec<&Entry> = upcoming
+                .into_iter()
+                .filter(|(t, _)| *t == time)
+                .map(|(_, entry)| entry)
+                .collect();
+            if let Ok(entry) =
+                candidates.choose_weighted(&mut rand::thread_rng(), |entry| entry.weight)
+            {
+                match entry.pick_file() {
+                    Ok(path) => {
+                        return Some(Due {
+                            time,
+                            path,
+                            ducking: entry.ducking,
+                        })
+                    }
+                    Err(e) => tracing::error!("Error picking interstitial: {}", e),
+                }
+            }
+            after = time;
+        }
+        None
+    }
+}
+
+/// The last second of the day of `time`.
+fn end_of_day(time: &DateTime<Local>) -> DateTime<Local> {
+    time.date_naive()
+        .and_hms_opt(23, 59, 59)
+        .and_then(|end| end.and_local_timezone(Local).latest())
+        // Clocks skipping the end of the day, go on from the time itself
+        .unwrap_or(*time)
+}
+
+/// Files in the interstitial folders, with their modification times.
+type Fingerprint = Vec<(PathBuf, Option<std::time::SystemTime>)>;
+
+fn fingerprint(folders: &[PathBuf]) -> Fingerprint {
+    let mut files = Vec::new();
+    let mut folders = folders.to_vec();
+    while let Some(folder) = folders.pop() {
+        let Ok(entries) = std::fs::read_dir(&folder) else {
+            continue;
+        };
+        for entry in entries.filter_map(|v| v.ok()) {
+            let path = entry.path();
+            if path.is_dir() {
+                folders.push(path.clone());
+            }
+            let modified = entry.metadata().and_then(|m| m.modified()).ok();
+            files.push((path, modified));
+        }
+    }
+    files.sort();
+    files
+}
+
+/// Poll the folders until a file was added, removed, renamed or changed.
+async fn wait_for_changes(folders: &[PathBuf], fingerprint: &mut Fingerprint) {
+    loop {
+        tokio::time::sleep(RELOAD_INTERVAL).await;
+        let current = tokio::task::block_in_place(|| self::fingerprint(folders));
+        if current != *fingerprint {
+            *fingerprint = current;
+            return;
+        }
+    }
+}
+
+/// How interstitials are mixed with the music.
+#[derive(Debug, Clone, Copy)]
+pub enum Mode {
+    /// Play at the scheduled time over the music, which is faded down.
+    Duck,
+    /// Play between two songs, at most the tolerance after the scheduled time.
+    Boundary,
+}
+
+impl std::str::FromStr for Mode {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "duck" => Ok(Mode::Duck),
+            "boundary" => Ok(Mode::Boundary),
+            _ => anyhow::bail!("Unknown interstitial mode {s:?}, expected duck or boundary"),
+        }
+    }
+}
+
+pub fn load_sound(path: &Path) -> anyhow::Result<Box<dyn Sound>> {
+    Ok(tokio::task::block_in_place(|| {
+        awedio::sounds::open_file(path)
+    })?)
+}
+
+/// Duck the music, play the sound over it and bring the music back up.
+pub async fn play_ducked(
+    sound: Box<dyn Sound>,
+    profile: ducking::Profile,
+    mixer_controller: &mut Controller<SoundMixer>,
+    ducker_controller: &mut DuckerController,
+) {
+    let (sound, level): (Box<dyn Sound>, _) = if profile.sidechain {
+        let (sound, level) = ducking::Sidechain::new(sound);
+        (Box::new(sound), Some(level))
+    } else {
+        (sound, None)
+    };
+    let (sound, completion_notifier) = sound.with_async_completion_notifier();
+
+    let hold = ducker_controller.duck(profile, level).await;
+    mixer_controller.add(Box::new(sound));
+    let _ = completion_notifier.await;
+    ducker_controller.release(hold);
+}
+
+/// Queue the sound right after the current song. Returns `false` if it did
+/// not start within `tolerance`, in which case it was taken out of the queue
+/// again.
+pub async fn play_at_boundary(
+    sound: Box<dyn Sound>,
+    name: String,
+    tolerance: Duration,
+    player_controller: &mut PlayerController,
+) -> bool {
+    let mut events = player_controller.subscribe();
+    player_controller.insert_next(sound, Track::Interstitial(name.clone()));
+
+    let started = tokio::time::timeout(tolerance, async {
+        loop {
+            match events.recv().await {
+                Ok(PlayerEvent::Started(Track::Interstitial(started))) if started == name => {
+                    return true
+                }
+                Err(RecvError::Closed) => return false,
+                _ => {}
+            }
+        }
+    })
+    .await;
+    if let Ok(started) = started {
+        return started;
+    }
+    // Only if it did not start in the meantime
+    !player_controller.remove_pending_interstitial(name).await
+}
+
+/// Play the scheduled interstitials, either over the music or between songs.
+/// Nothing plays while a live source is on air.
+#[allow(clippy::too_many_arguments)]
+pub async fn run(
+    folders: Vec<PathBuf>,
+    mode: Mode,
+    tolerance: Duration,
+    mut mixer_controller: Controller<SoundMixer>,
+    mut ducker_controller: DuckerController,
+    mut player_controller: PlayerController,
+    events: EventBus,
+    live_on_air: Arc<AtomicBool>,
+) {
+    let mut fingerprint = fingerprint(&folders);
+    let mut schedule = Schedule::load_all(&folders);
+
+    loop {
+        let now = Local::now();
+        let next = schedule.next(&now);
+        match &next {
+            Some(due) => tracing::info!("Next Internstitial time {}: {:?}", due.time, due.path),
+            None => tracing::info!("No interstitials scheduled, waiting for changes"),
+        }
+
+        let wait = async {
+            match &next {
+                Some(due) => {
+                    tokio::time::sleep((due.time - now).to_std().unwrap_or_default()).await
+                }
+                None => std::future::pending().await,
+            }
+        };
+        tokio::select! {
+            _ = wait => {}
+            _ = wait_for_changes(&folders, &mut fingerprint) => {
+                tracing::info!("Interstitials changed, reloading");
+                schedule = Schedule::load_all(&folders);
+                continue;
+            }
+        }
+        let Some(Due {
+            path: next_path,
+            ducking,
+            ..
+        }) = next
+        else {
+            continue;
+        };
+        if live_on_air.load(Ordering::SeqCst) {
+            tracing::info!("Live source on air, skipping interstitial {:?}", next_path);
+            continue;
+        }
+
+        tracing::info!("Playing interstitial {:?}", next_path);
+
+        let sound = match load_sound(&next_path) {
+            Ok(sound) => sound,
+            Err(e) => {
+                tracing::error!("Error playing interstitial: {}", e);
+                continue;
+            }
+        };
+        let name = next_path.display().to_string();
+        match mode {
+            Mode::Duck => {
+                events.publish(Event::InterstitialStarted { name: name.clone() });
+                play_ducked(
+                    sound,
+                    ducking,
+                    &mut mixer_controller,
+                    &mut ducker_controller,
+                )
+                .await;
+                events.publish(Event::InterstitialEnded { name });
+            }
+            Mode::Boundary => {
+                let played =
+                    play_at_boundary(sound, name.clone(), tolerance, &mut player_controller).await;
+                if !played {
+                    tracing::info!("No song ended in time, playing interstitial over the music");
+                    match load_sound(&next_path) {
+                        Ok(sound) => {
+                            events.publish(Event::InterstitialStarted { name: name.clone() });
+                            play_ducked(
+                                sound,
+                                ducking,
+                                &mut mixer_controller,
+                                &mut ducker_controller,
+                            )
+                            .await;
+                            events.publish(Event::InterstitialEnded { name });
+                        }
+                        Err(e) => tracing::error!("Error playing interstitial: {}", e),
+                    }
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::TimeZone;
+
+    use super::*;
+
+    fn entry(cron: &str, weekdays: &[&str], from: Option<&str>, until: Option<&str>) -> Entry {
+        let entry = ManifestEntry {
+            file: PathBuf::from("jingle.mp3"),
+            cron: cron.to_owned(),
+            weekdays: weekdays.iter().map(|d| d.to_string()).collect(),
+            from: from.map(str::to_owned),
+            until: until.map(str::to_owned),
+            weight: 1,
+            ducking: None,
+        };
+        Entry::from_manifest(Path::new("/interstitials"), entry, &HashMap::new()).unwrap()
+    }
+
+    fn time(month: u32, day: u32, hour: u32, minute: u32) -> DateTime<Local> {
+        Local
+            .with_ymd_and_hms(2025, month, day, hour, minute, 0)
+            .unwrap()
+    }
+
+    #[test]
+    fn parses_month_days() {
+        assert_eq!(parse_month_day("02-29").unwrap(), (2, 29));
+        assert!(parse_month_day("02-30").is_err());
+        assert!(parse_month_day("12").is_err());
+    }
+
+    #[test]
+    fn plays_at_cron_times() {
+        let entry = entry("0 30 * * * *", &[], None, None);
+        assert_eq!(entry.next(&time(3, 1, 12, 0)), Some(time(3, 1, 12, 30)));
+        assert_eq!(entry.next(&time(3, 1, 12, 30)), Some(time(3, 1, 13, 30)));
+    }
+
+    #[test]
+    fn skips_to_weekdays() {
+        // The 1st of March 2025 is a Saturday
+        let entry = entry("0 0 8 * * *", &["Mon"], None, None);
+        assert_eq!(entry.next(&time(3, 1, 12, 0)), Some(time(3, 3, 8, 0)));
+    }
+
+    #[test]
+    fn skips_to_date_range() {
+        // Every second, months ahead, would take millions of steps one by one
+        let entry = entry("* * * * * *", &[], Some("12-24"), Some("12-26"));
+        assert_eq!(entry.next(&time(3, 1, 12, 0)), Some(time(12, 24, 0, 0)));
+        assert_eq!(
+            entry.next(&time(12, 25, 9, 0)),
+            Some(time(12, 25, 9, 0) + chrono::Duration::try_seconds(1).unwrap())
+        );
+    }
+
+    #[test]
+    fn date_range_wraps_around_new_year() {
+        let entry = entry("0 0 8 * * *", &[], Some("12-31"), Some("01-01"));
+        assert_eq!(entry.next(&time(3, 1, 12, 0)), Some(time(12, 31, 8, 0)));
+        assert_eq!(entry.next(&time(1, 1, 7, 0)), Some(time(1, 1, 8, 0)));
+    }
+
+    #[test]
+    fn never_matching_entry_gives_up() {
+        // The 30th never falls on the 1st of February
+        let entry = entry("0 0 8 30 * *", &[], Some("02-01"), Some("02-01"));
+        assert_eq!(entry.next(&time(3, 1, 12, 0)), None);
+    }
+
+    #[test]
+    fn schedule_picks_earliest_entry() {
+        let schedule = Schedule {
+            entries: vec![
+                entry("0 0 9 * * *", &[], None, None),
+                entry("0 0 8 * * *", &[], None, None),
+            ],
+        };
+        let due = schedule.next(&time(3, 1, 7, 0)).unwrap();
+        assert_eq!(due.time, time(3, 1, 8, 0));
+    }
+
+    #[test]
+    fn schedule_without_weights_gives_up() {
+        let mut entry = entry("* * * * * *", &[], None, None);
+        entry.weight = 0;
+        let schedule = Schedule {
+            entries: vec![entry],
+        };
+        assert!(schedule.next(&time(3, 1, 7, 0)).is_none());
+    }
+}
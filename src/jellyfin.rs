@@ -1,13 +1,64 @@
 use bytes::Buf;
-use serde::Deserialize;
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+use std::time::Duration;
+
+const CLIENT_NAME: &str = "jellyfin-radio";
+const CLIENT_VERSION: &str = env!("CARGO_PKG_VERSION");
+/// Images kept in memory, enough for all covers the web player shows.
+const IMAGE_CACHE_SIZE: usize = 64;
+
+/// How the client gets its access token.
+pub enum Credentials {
+    /// A server-wide API key, created in the Jellyfin dashboard.
+    ApiKey(String),
+    /// Log in as a user via `/Users/AuthenticateByName`.
+    Password { username: String, password: String },
+    /// Log in as a user by entering a code in an already logged in Jellyfin client.
+    QuickConnect,
+}
 
 pub struct JellyfinClient {
-    api_token: String,
     base_url: String,
+    credentials: Credentials,
+    device_id: String,
+    token_file: Option<std::path::PathBuf>,
+    access_token: tokio::sync::RwLock<Option<String>>,
+    login_lock: tokio::sync::Mutex<()>,
     client: reqwest::Client,
+    images: std::sync::Mutex<ImageCache>,
+}
+
+/// Recently fetched images by item id and size, least recently used first.
+#[derive(Default)]
+struct ImageCache(std::collections::VecDeque<((String, u32), bytes::Bytes)>);
+
+impl ImageCache {
+    /// The cached image, which then counts as the most recently used.
+    fn get(&mut self, key: &(String, u32)) -> Option<bytes::Bytes> {
+        let index = self.0.iter().position(|(k, _)| k == key)?;
+        let entry = self.0.remove(index)?;
+        let image = entry.1.clone();
+        self.0.push_back(entry);
+        Some(image)
+    }
+
+    fn insert(&mut self, key: (String, u32), image: bytes::Bytes) {
+        self.0.retain(|(k, _)| *k != key);
+        if self.0.len() >= IMAGE_CACHE_SIZE {
+            self.0.pop_front();
+        }
+        self.0.push_back((key, image));
+    }
 }
 
 #[derive(Deserialize)]
+struct AuthenticationResult {
+    #[serde(rename(deserialize = "AccessToken"))]
+    access_token: String,
+}
+
+#[derive(Deserialize, Clone)]
 pub struct Audio {
     #[serde(rename(deserialize = "Id"))]
     pub id: String,
@@ -15,6 +66,41 @@ pub struct Audio {
     pub name: String,
     #[serde(rename(deserialize = "Artists"))]
     pub artists: Vec<String>,
+    #[serde(rename(deserialize = "Album"), default)]
+    pub album: Option<String>,
+    #[serde(rename(deserialize = "AlbumId"), default)]
+    pub album_id: Option<String>,
+    #[serde(rename(deserialize = "RunTimeTicks"), default)]
+    pub run_time_ticks: Option<u64>,
+}
+
+impl Audio {
+    pub fn duration(&self) -> Option<Duration> {
+        self.run_time_ticks
+            .map(|ticks| Duration::from_nanos(ticks * 100))
+    }
+}
+
+#[derive(Deserialize)]
+pub struct Item {
+    #[serde(rename(deserialize = "Id"))]
+    pub id: String,
+    #[serde(rename(deserialize = "Name"))]
+    pub name: String,
+}
+
+/// An audio item with the metadata used to schedule it as an interstitial.
+#[derive(Deserialize)]
+pub struct Clip {
+    #[serde(rename(deserialize = "Id"))]
+    pub id: String,
+    #[serde(rename(deserialize = "Name"))]
+    pub name: String,
+    #[serde(rename(deserialize = "Tags"), default)]
+    pub tags: Vec<String>,
+    /// Changes whenever the item does.
+    #[serde(rename(deserialize = "Etag"), default)]
+    pub etag: Option<String>,
 }
 
 #[derive(Deserialize)]
@@ -41,30 +127,277 @@ pub struct User {
 pub struct UserPolicy {
     #[serde(rename(deserialize = "IsAdministrator"))]
     pub is_administrator: bool,
+    #[serde(rename(deserialize = "IsDisabled"), default)]
+    pub is_disabled: bool,
+    #[serde(rename(deserialize = "EnableMediaPlayback"), default = "default_true")]
+    pub enable_media_playback: bool,
+    #[serde(
+        rename(deserialize = "EnableContentDownloading"),
+        default = "default_true"
+    )]
+    pub enable_content_downloading: bool,
+    #[serde(rename(deserialize = "EnableAllFolders"), default = "default_true")]
+    pub enable_all_folders: bool,
+    #[serde(rename(deserialize = "EnabledFolders"), default)]
+    pub enabled_folders: Vec<String>,
+}
+
+fn default_true() -> bool {
+    true
+}
+
+impl User {
+    /// Check that the station can play music from `collection_id` as this user.
+    pub fn validate(&self, collection_id: &str) -> anyhow::Result<()> {
+        if self.policy.is_disabled {
+            anyhow::bail!("User {} is disabled", self.name);
+        }
+        if !self.policy.enable_media_playback {
+            anyhow::bail!("User {} is not allowed to play media", self.name);
+        }
+        if !self.policy.enable_content_downloading {
+            anyhow::bail!(
+                "User {} is not allowed to download media, which is required to stream songs",
+                self.name
+            );
+        }
+        if !self.policy.enable_all_folders
+            && !self
+                .policy
+                .enabled_folders
+                .iter()
+                .any(|f| f == collection_id)
+        {
+            anyhow::bail!("User {} has no access to the collection", self.name);
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub enum PlaybackReport {
+    Started,
+    Progress,
+    Stopped,
+}
+
+/// Jellyfin positions are counted in ticks of 100 ns.
+fn to_ticks(duration: Duration) -> u64 {
+    (duration.as_nanos() / 100) as u64
+}
+
+#[derive(Debug, Clone, Copy)]
+pub enum ItemKind {
+    Song,
+    Artist,
+    Genre,
+    Album,
+    Playlist,
 }
 
 impl JellyfinClient {
-    pub fn new(base_url: String, api_token: String) -> Self {
+    pub fn new(
+        base_url: String,
+        credentials: Credentials,
+        device_id: String,
+        token_file: Option<std::path::PathBuf>,
+    ) -> Self {
+        let access_token = match &credentials {
+            Credentials::ApiKey(key) => Some(key.clone()),
+            _ => token_file
+                .as_ref()
+                .and_then(|path| std::fs::read_to_string(path).ok())
+                .map(|token| token.trim().to_owned())
+                .filter(|token| !token.is_empty()),
+        };
         Self {
             base_url,
-            api_token,
+            credentials,
+            device_id,
+            token_file,
+            access_token: tokio::sync::RwLock::new(access_token),
+            login_lock: tokio::sync::Mutex::new(()),
             client: reqwest::Client::new(),
+            images: Default::default(),
         }
     }
 
-    pub async fn users(&self) -> anyhow::Result<Vec<User>> {
-        let url = format!("{}/Users", self.base_url);
-        let response: Vec<User> = self
-            .client
-            .get(url)
+    /// Whether the client acts as a logged in user instead of using an API key.
+    pub fn is_user_session(&self) -> bool {
+        !matches!(self.credentials, Credentials::ApiKey(_))
+    }
+
+    fn authorization(&self, token: Option<&str>) -> String {
+        let mut header = format!(
+            "MediaBrowser Client=\"{CLIENT_NAME}\", Device=\"{CLIENT_NAME}\", DeviceId=\"{}\", Version=\"{CLIENT_VERSION}\"",
+            self.device_id
+        );
+        if let Some(token) = token {
+            header.push_str(&format!(", Token=\"{token}\""));
+        }
+        header
+    }
+
+    /// Send an authorized request. If the server rejects the access token,
+    /// log in again and retry once.
+    async fn send(
+        &self,
+        request: impl Fn(&reqwest::Client) -> reqwest::RequestBuilder,
+    ) -> anyhow::Result<reqwest::Response> {
+        let token = match self.access_token.read().await.clone() {
+            Some(token) => token,
+            None => self.login(None).await?,
+        };
+        let response = request(&self.client)
             .header(
-                "Authorization",
-                format!("MediaBrowser Token=\"{}\"", self.api_token),
+                reqwest::header::AUTHORIZATION,
+                self.authorization(Some(&token)),
+            )
+            .send()
+            .await?;
+        if response.status() != reqwest::StatusCode::UNAUTHORIZED || !self.is_user_session() {
+            return Ok(response.error_for_status()?);
+        }
+
+        tracing::warn!("Jellyfin rejected the access token, logging in again");
+        let token = self.login(Some(&token)).await?;
+        let response = request(&self.client)
+            .header(
+                reqwest::header::AUTHORIZATION,
+                self.authorization(Some(&token)),
             )
             .send()
-            .await?
-            .json()
             .await?;
+        Ok(response.error_for_status()?)
+    }
+
+    /// Get a new access token. `stale` is the token which was rejected, if
+    /// another task already replaced it the new token is returned right away.
+    async fn login(&self, stale: Option<&str>) -> anyhow::Result<String> {
+        let _guard = self.login_lock.lock().await;
+        if let Some(token) = self.access_token.read().await.as_deref() {
+            if Some(token) != stale {
+                return Ok(token.to_owned());
+            }
+        }
+
+        let result: AuthenticationResult = match &self.credentials {
+            Credentials::ApiKey(_) => anyhow::bail!("The API key was rejected"),
+            Credentials::Password { username, password } => {
+                #[derive(Serialize)]
+                struct AuthenticateByName<'a> {
+                    #[serde(rename = "Username")]
+                    username: &'a str,
+                    #[serde(rename = "Pw")]
+                    password: &'a str,
+                }
+
+                tracing::info!("Logging in to Jellyfin as {}", username);
+                self.client
+                    .post(format!("{}/Users/AuthenticateByName", self.base_url))
+                    .header(reqwest::header::AUTHORIZATION, self.authorization(None))
+                    .json(&AuthenticateByName { username, password })
+                    .send()
+                    .await?
+                    .error_for_status()?
+                    .json()
+                    .await?
+            }
+            Credentials::QuickConnect => self.quick_connect().await?,
+        };
+
+        if let Some(path) = &self.token_file {
+            if let Err(e) = write_private(path, &result.access_token).await {
+                tracing::warn!("Could not store access token at {:?}: {}", path, e);
+            }
+        }
+        *self.access_token.write().await = Some(result.access_token.clone());
+        Ok(result.access_token)
+    }
+
+    async fn quick_connect(&self) -> anyhow::Result<AuthenticationResult> {
+        #[derive(Deserialize)]
+        struct QuickConnectResult {
+            #[serde(rename(deserialize = "Secret"))]
+            secret: String,
+            #[serde(rename(deserialize = "Code"))]
+            code: String,
+            #[serde(rename(deserialize = "Authenticated"))]
+            authenticated: bool,
+        }
+
+        #[derive(Serialize)]
+        struct QuickConnectDto<'a> {
+            #[serde(rename = "Secret")]
+            secret: &'a str,
+        }
+
+        loop {
+            let initiated: QuickConnectResult = self
+                .client
+                .post(format!("{}/QuickConnect/Initiate", self.base_url))
+                .header(reqwest::header::AUTHORIZATION, self.authorization(None))
+                .send()
+                .await?
+                .error_for_status()?
+                .json()
+                .await?;
+            tracing::warn!(
+                "Enter the Quick Connect code {} in a logged in Jellyfin client to authorize jellyfin-radio",
+                initiated.code
+            );
+
+            let authenticated = loop {
+                tokio::time::sleep(Duration::from_secs(5)).await;
+                let response = self
+                    .client
+                    .get(format!("{}/QuickConnect/Connect", self.base_url))
+                    .query(&[("Secret", &initiated.secret)])
+                    .header(reqwest::header::AUTHORIZATION, self.authorization(None))
+                    .send()
+                    .await?;
+                if !response.status().is_success() {
+                    // The request expired, start over with a new code
+                    break false;
+                }
+                let state: QuickConnectResult = response.json().await?;
+                if state.authenticated {
+                    break true;
+                }
+            };
+            if !authenticated {
+                tracing::info!("Quick Connect code expired, requesting a new one");
+                continue;
+            }
+
+            return Ok(self
+                .client
+                .post(format!(
+                    "{}/Users/AuthenticateWithQuickConnect",
+                    self.base_url
+                ))
+                .header(reqwest::header::AUTHORIZATION, self.authorization(None))
+                .json(&QuickConnectDto {
+                    secret: &initiated.secret,
+                })
+                .send()
+                .await?
+                .error_for_status()?
+                .json()
+                .await?);
+        }
+    }
+
+    /// The user the client is logged in as.
+    pub async fn me(&self) -> anyhow::Result<User> {
+        let url = format!("{}/Users/Me", self.base_url);
+        let response: User = self.send(|client| client.get(&url)).await?.json().await?;
+        Ok(response)
+    }
+
+    pub async fn users(&self) -> anyhow::Result<Vec<User>> {
+        let url = format!("{}/Users", self.base_url);
+        let response: Vec<User> = self.send(|client| client.get(&url)).await?.json().await?;
         Ok(response)
     }
 
@@ -76,17 +409,7 @@ impl JellyfinClient {
         }
 
         let url = format!("{}/Users/{user_id}/Views", self.base_url);
-        let response: ViewList = self
-            .client
-            .get(url)
-            .header(
-                "Authorization",
-                format!("MediaBrowser Token=\"{}\"", self.api_token),
-            )
-            .send()
-            .await?
-            .json()
-            .await?;
+        let response: ViewList = self.send(|client| client.get(&url)).await?.json().await?;
         Ok(response.items)
     }
 
@@ -99,40 +422,276 @@ impl JellyfinClient {
 
         let url = format!("{}/Users/{user_id}/Items", self.base_url);
         let mut response: AudioList = self
-            .client
-            .get(url)
-            .query(&[
-                ("ParentId", collection_id),
-                ("Filters", "IsNotFolder"),
-                ("Recursive", "true"),
-                ("SortBy", "Random"),
-                ("MediaTypes", "Audio"),
-                ("Limit", "1"),
-                ("ExcludeLocationTypes", "Virtual"),
-                ("CollapseBoxSetItems", "false"),
-            ])
-            .header(
-                "Authorization",
-                format!("MediaBrowser Token=\"{}\"", self.api_token),
-            )
-            .send()
+            .send(|client| {
+                client.get(&url).query(&[
+                    ("ParentId", collection_id),
+                    ("Filters", "IsNotFolder"),
+                    ("Recursive", "true"),
+                    ("SortBy", "Random"),
+                    ("MediaTypes", "Audio"),
+                    ("Limit", "1"),
+                    ("ExcludeLocationTypes", "Virtual"),
+                    ("CollapseBoxSetItems", "false"),
+                ])
+            })
             .await?
             .json()
             .await?;
         response.items.pop().ok_or(anyhow::anyhow!("No item found"))
     }
 
-    pub async fn fetch_audio(&self, audio: Audio) -> anyhow::Result<Box<dyn awedio::Sound>> {
-        let url = format!("{}/Items/{}/Download", self.base_url, audio.id);
-        let response = self
-            .client
-            .get(url)
-            .header(
-                "Authorization",
-                format!("MediaBrowser Token=\"{}\"", self.api_token),
-            )
-            .send()
+    pub async fn instant_mix(
+        &self,
+        user_id: &str,
+        item_id: &str,
+        limit: u32,
+    ) -> anyhow::Result<Vec<Audio>> {
+        #[derive(Deserialize)]
+        struct AudioList {
+            #[serde(rename(deserialize = "Items"))]
+            items: Vec<Audio>,
+        }
+
+        let url = format!("{}/Items/{item_id}/InstantMix", self.base_url);
+        let response: AudioList = self
+            .send(|client| {
+                client
+                    .get(&url)
+                    .query(&[("UserId", user_id), ("Limit", &limit.to_string())])
+            })
+            .await?
+            .json()
+            .await?;
+        Ok(response.items)
+    }
+
+    /// Which of the given items are in the collection.
+    pub async fn in_collection(
+        &self,
+        user_id: &str,
+        collection_id: &str,
+        item_ids: &[&str],
+    ) -> anyhow::Result<HashSet<String>> {
+        #[derive(Deserialize)]
+        struct ItemList {
+            #[serde(rename(deserialize = "Items"))]
+            items: Vec<Item>,
+        }
+
+        if item_ids.is_empty() {
+            return Ok(HashSet::new());
+        }
+        let url = format!("{}/Users/{user_id}/Items", self.base_url);
+        let ids = item_ids.join(",");
+        let response: ItemList = self
+            .send(|client| {
+                client.get(&url).query(&[
+                    ("ParentId", collection_id),
+                    ("Ids", &ids),
+                    ("Recursive", "true"),
+                ])
+            })
+            .await?
+            .json()
+            .await?;
+        Ok(response.items.into_iter().map(|i| i.id).collect())
+    }
+
+    /// Look up an item by name. Songs and albums are searched in the given
+    /// collection, everything else across the whole library of the user.
+    pub async fn find_item(
+        &self,
+        user_id: &str,
+        collection_id: &str,
+        kind: ItemKind,
+        name: &str,
+    ) -> anyhow::Result<Item> {
+        #[derive(Deserialize)]
+        struct ItemList {
+            #[serde(rename(deserialize = "Items"))]
+            items: Vec<Item>,
+        }
+
+        let response: ItemList = self
+            .send(|client| {
+                let request = match kind {
+                    ItemKind::Song => client
+                        .get(format!("{}/Users/{user_id}/Items", self.base_url))
+                        .query(&[
+                            ("ParentId", collection_id),
+                            ("IncludeItemTypes", "Audio"),
+                            ("Recursive", "true"),
+                        ]),
+                    ItemKind::Artist => client
+                        .get(format!("{}/Artists", self.base_url))
+                        .query(&[("UserId", user_id)]),
+                    ItemKind::Genre => client
+                        .get(format!("{}/MusicGenres", self.base_url))
+                        .query(&[("UserId", user_id)]),
+                    ItemKind::Album => client
+                        .get(format!("{}/Users/{user_id}/Items", self.base_url))
+                        .query(&[
+                            ("ParentId", collection_id),
+                            ("IncludeItemTypes", "MusicAlbum"),
+                            ("Recursive", "true"),
+                        ]),
+                    ItemKind::Playlist => client
+                        .get(format!("{}/Users/{user_id}/Items", self.base_url))
+                        .query(&[("IncludeItemTypes", "Playlist"), ("Recursive", "true")]),
+                };
+                request.query(&[("SearchTerm", name), ("Limit", "10")])
+            })
+            .await?
+            .json()
+            .await?;
+
+        // Prefer an exact match, the search also returns partial matches.
+        let mut items = response.items;
+        let position = items
+            .iter()
+            .position(|i| i.name.eq_ignore_ascii_case(name))
+            .unwrap_or(0);
+        if items.is_empty() {
+            anyhow::bail!("No {kind:?} named {name:?} found");
+        }
+        Ok(items.swap_remove(position))
+    }
+
+    /// The songs of an album in track order.
+    pub async fn album_audio(&self, user_id: &str, album_id: &str) -> anyhow::Result<Vec<Audio>> {
+        #[derive(Deserialize)]
+        struct AudioList {
+            #[serde(rename(deserialize = "Items"))]
+            items: Vec<Audio>,
+        }
+
+        let url = format!("{}/Users/{user_id}/Items", self.base_url);
+        let response: AudioList = self
+            .send(|client| {
+                client.get(&url).query(&[
+                    ("ParentId", album_id),
+                    ("MediaTypes", "Audio"),
+                    ("Recursive", "true"),
+                    ("SortBy", "ParentIndexNumber,IndexNumber,SortName"),
+                ])
+            })
+            .await?
+            .json()
+            .await?;
+        Ok(response.items)
+    }
+
+    /// The songs of a playlist in playlist order.
+    pub async fn playlist_audio(
+        &self,
+        user_id: &str,
+        playlist_id: &str,
+    ) -> anyhow::Result<Vec<Audio>> {
+        #[derive(Deserialize)]
+        struct AudioList {
+            #[serde(rename(deserialize = "Items"))]
+            items: Vec<Audio>,
+        }
+
+        let url = format!("{}/Playlists/{playlist_id}/Items", self.base_url);
+        let response: AudioList = self
+            .send(|client| {
+                client
+                    .get(&url)
+                    .query(&[("UserId", user_id), ("MediaTypes", "Audio")])
+            })
+            .await?
+            .json()
+            .await?;
+        Ok(response.items)
+    }
+
+    /// The audio items of a collection or playlist, with their tags.
+    pub async fn clips(&self, user_id: &str, parent_id: &str) -> anyhow::Result<Vec<Clip>> {
+        #[derive(Deserialize)]
+        struct ClipList {
+            #[serde(rename(deserialize = "Items"))]
+            items: Vec<Clip>,
+        }
+
+        let url = format!("{}/Users/{user_id}/Items", self.base_url);
+        let response: ClipList = self
+            .send(|client| {
+                client.get(&url).query(&[
+                    ("ParentId", parent_id),
+                    ("MediaTypes", "Audio"),
+                    ("Recursive", "true"),
+                    ("Fields", "Tags,Etag"),
+                ])
+            })
+            .await?
+            .json()
             .await?;
+        Ok(response.items)
+    }
+
+    pub async fn report_playback(
+        &self,
+        report: PlaybackReport,
+        item_id: &str,
+        position: Duration,
+        paused: bool,
+    ) -> anyhow::Result<()> {
+        #[derive(Serialize)]
+        struct PlaybackInfo<'a> {
+            #[serde(rename = "ItemId")]
+            item_id: &'a str,
+            #[serde(rename = "PositionTicks")]
+            position_ticks: u64,
+            #[serde(rename = "IsPaused")]
+            is_paused: bool,
+            #[serde(rename = "CanSeek")]
+            can_seek: bool,
+            #[serde(rename = "PlayMethod")]
+            play_method: &'a str,
+        }
+
+        let path = match report {
+            PlaybackReport::Started => "Playing",
+            PlaybackReport::Progress => "Playing/Progress",
+            PlaybackReport::Stopped => "Playing/Stopped",
+        };
+        let url = format!("{}/Sessions/{path}", self.base_url);
+        let info = PlaybackInfo {
+            item_id,
+            position_ticks: to_ticks(position),
+            is_paused: paused,
+            can_seek: false,
+            play_method: "DirectPlay",
+        };
+        self.send(|client| client.post(&url).json(&info)).await?;
+        Ok(())
+    }
+
+    /// Mark an item as played, which updates the play count and last played date.
+    pub async fn mark_played(&self, user_id: &str, item_id: &str) -> anyhow::Result<()> {
+        let url = format!("{}/Users/{user_id}/PlayedItems/{item_id}", self.base_url);
+        self.send(|client| client.post(&url)).await?;
+        Ok(())
+    }
+
+    pub async fn fetch_audio(&self, audio: &Audio) -> anyhow::Result<Box<dyn awedio::Sound>> {
+        let (extension, body) = self.download(&audio.id).await?;
+
+        let decoder = awedio::sounds::decoders::SymphoniaDecoder::new(
+            Box::new(symphonia::core::io::ReadOnlySource::new(body.reader())),
+            extension.as_deref(),
+        )?;
+
+        let decoder = Box::new(decoder);
+
+        Ok(decoder)
+    }
+
+    /// Download the original file of an item, along with its extension.
+    pub async fn download(&self, item_id: &str) -> anyhow::Result<(Option<String>, bytes::Bytes)> {
+        let url = format!("{}/Items/{}/Download", self.base_url, item_id);
+        let response = self.send(|client| client.get(&url)).await?;
         let filename = response
             .headers()
             .get(reqwest::header::CONTENT_DISPOSITION)
@@ -149,14 +708,100 @@ impl JellyfinClient {
             .map(String::from)
             .map(|s| s.replace("\"", ""));
         let body = response.bytes().await?;
+        Ok((extension, body))
+    }
 
-        let decoder = awedio::sounds::decoders::SymphoniaDecoder::new(
-            Box::new(symphonia::core::io::ReadOnlySource::new(body.reader())),
-            extension.as_deref(),
-        )?;
+    /// The primary image of an item as JPEG, scaled down to fit `size`
+    /// pixels square. Images are cached, as clients tend to ask for the same
+    /// few covers over and over.
+    pub async fn image(&self, item_id: &str, size: u32) -> anyhow::Result<bytes::Bytes> {
+        let key = (item_id.to_owned(), size);
+        if let Some(image) = self.images.lock().unwrap().get(&key) {
+            return Ok(image);
+        }
 
-        let decoder = Box::new(decoder);
+        let url = format!("{}/Items/{}/Images/Primary", self.base_url, item_id);
+        let response = self
+            .send(|client| {
+                client.get(&url).query(&[
+                    ("maxWidth", size.to_string()),
+                    ("maxHeight", size.to_string()),
+                    ("format", "Jpg".to_owned()),
+                    ("quality", "90".to_owned()),
+                ])
+            })
+            .await?;
+        let image = response.bytes().await?;
+        self.images.lock().unwrap().insert(key, image.clone());
+        Ok(image)
+    }
+}
 
-        Ok(decoder)
+/// Write a file only the current user can read, as it holds a secret.
+async fn write_private(path: &std::path::Path, contents: &str) -> std::io::Result<()> {
+    use tokio::io::AsyncWriteExt;
+
+    let mut options = tokio::fs::OpenOptions::new();
+    options.write(true).create(true).truncate(true);
+    #[cfg(unix)]
+    options.mode(0o600);
+    let mut file = options.open(path).await?;
+    // The mode only applies to new files, tighten existing ones as well
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        file.set_permissions(std::fs::Permissions::from_mode(0o600))
+            .await?;
+    }
+    file.write_all(contents.as_bytes()).await?;
+    file.flush().await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn key(id: usize) -> (String, u32) {
+        (id.to_string(), 512)
+    }
+
+    #[test]
+    fn image_cache_drops_least_recently_used() {
+        let mut cache = ImageCache::default();
+        for id in 0..IMAGE_CACHE_SIZE {
+            cache.insert(key(id), bytes::Bytes::from(id.to_string()));
+        }
+        // Using the oldest keeps it, the next oldest goes instead
+        assert_eq!(cache.get(&key(0)), Some(bytes::Bytes::from("0")));
+        cache.insert(key(IMAGE_CACHE_SIZE), bytes::Bytes::new());
+        assert!(cache.get(&key(0)).is_some());
+        assert!(cache.get(&key(1)).is_none());
+        assert_eq!(cache.0.len(), IMAGE_CACHE_SIZE);
+    }
+
+    #[test]
+    fn image_cache_replaces_same_key() {
+        let mut cache = ImageCache::default();
+        cache.insert(key(0), bytes::Bytes::from("old"));
+        cache.insert(key(0), bytes::Bytes::from("new"));
+        assert_eq!(cache.get(&key(0)), Some(bytes::Bytes::from("new")));
+        assert_eq!(cache.0.len(), 1);
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn token_file_is_private() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let path =
+            std::env::temp_dir().join(format!("jellyfin-radio-token-{}", std::process::id()));
+        std::fs::write(&path, "old").unwrap();
+        std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o644)).unwrap();
+        write_private(&path, "token").await.unwrap();
+        let mode = std::fs::metadata(&path).unwrap().permissions().mode();
+        let contents = std::fs::read_to_string(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(mode & 0o777, 0o600);
+        assert_eq!(contents, "token");
     }
 }
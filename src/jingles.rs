@@ -0,0 +1,125 @@
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+use rand::seq::SliceRandom;
+use tokio::sync::broadcast::error::RecvError;
+
+use crate::interstitial;
+use crate::player::{PlayerController, PlayerEvent, Track};
+
+pub const JINGLE_FOLDER: &str = "jingles";
+
+/// When to play a jingle. If both are set, whichever is reached first wins.
+pub struct Rotation {
+    pub every_songs: Option<u32>,
+    pub every: Option<Duration>,
+}
+
+/// Queue station IDs and jingles from the jingle folders between songs.
+pub async fn run(
+    folders: Vec<PathBuf>,
+    rotation: Rotation,
+    mut player_controller: PlayerController,
+    live_on_air: Arc<AtomicBool>,
+) {
+    let mut events = player_controller.subscribe();
+    let mut songs_since_jingle = 0;
+    let mut last_jingle_at = tokio::time::Instant::now();
+    let mut last_jingle: Option<PathBuf> = None;
+    let mut queued: Option<String> = None;
+
+    loop {
+        let event = match events.recv().await {
+            Ok(event) => event,
+            Err(RecvError::Lagged(_)) => {
+                // The events of the queued jingle may be among the missed
+                // ones, ask the player whether it is still waiting
+                if let Some(name) = queued.take() {
+                    if player_controller
+                        .has_pending_interstitial(name.clone())
+                        .await
+                    {
+                        queued = Some(name);
+                    } else {
+                        songs_since_jingle = 0;
+                        last_jingle_at = tokio::time::Instant::now();
+                    }
+                }
+                continue;
+            }
+            Err(RecvError::Closed) => return,
+        };
+        match event {
+            PlayerEvent::Started(Track::Interstitial(name)) if Some(&name) == queued.as_ref() => {
+                queued = None;
+                songs_since_jingle = 0;
+                last_jingle_at = tokio::time::Instant::now();
+            }
+            PlayerEvent::Finished {
+                track: Track::Interstitial(name),
+                ..
+            }
+            | PlayerEvent::Skipped {
+                track: Track::Interstitial(name),
+                ..
+            } if Some(&name) == queued.as_ref() => {
+                queued = None;
+            }
+            PlayerEvent::Started(Track::Song(_)) if queued.is_none() => {
+                songs_since_jingle += 1;
+                let due = rotation
+                    .every_songs
+                    .is_some_and(|songs| songs_since_jingle >= songs)
+                    || rotation
+                        .every
+                        .is_some_and(|every| last_jingle_at.elapsed() >= every);
+                // Queued now, the jingle would play right after the live source
+                if !due || live_on_air.load(Ordering::SeqCst) {
+                    continue;
+                }
+
+                let path = match pick_jingle(&folders, last_jingle.as_deref()) {
+                    Ok(path) => path,
+                    Err(e) => {
+                        tracing::warn!("Error picking jingle: {}", e);
+                        continue;
+                    }
+                };
+                match interstitial::load_sound(&path) {
+                    Ok(sound) => {
+                        tracing::info!("Queuing jingle {:?}", path);
+                        let name = path.display().to_string();
+                        player_controller.insert_next(sound, Track::Interstitial(name.clone()));
+                        queued = Some(name);
+                        last_jingle = Some(path);
+                    }
+                    Err(e) => tracing::error!("Error loading jingle {:?}: {}", path, e),
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+/// Pick a random jingle, but never the one which played last.
+fn pick_jingle(folders: &[PathBuf], last: Option<&Path>) -> anyhow::Result<PathBuf> {
+    let files: Vec<PathBuf> = folders
+        .iter()
+        .filter_map(|folder| std::fs::read_dir(folder).ok())
+        .flatten()
+        .filter_map(|v| v.ok())
+        .map(|v| v.path())
+        .filter(|v| !v.is_dir())
+        .collect();
+    let candidates: Vec<&PathBuf> = if files.len() > 1 {
+        files.iter().filter(|f| Some(f.as_path()) != last).collect()
+    } else {
+        files.iter().collect()
+    };
+    candidates
+        .choose(&mut rand::thread_rng())
+        .map(|f| (*f).clone())
+        .ok_or(anyhow::anyhow!("No jingles in {:?}", folders))
+}
@@ -0,0 +1,172 @@
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
+
+use chrono::Timelike;
+
+use crate::interstitial::TIME_FOLDER;
+use crate::jellyfin::{Clip, ItemKind, JellyfinClient};
+use crate::jingles::JINGLE_FOLDER;
+
+/// How often the Jellyfin library is checked for new or changed clips.
+const SYNC_INTERVAL: Duration = Duration::from_secs(5 * 60);
+/// Subfolder of the cache path the clips are kept in. It is created and
+/// emptied by us, so nothing else in the cache path is ever touched.
+pub const CACHE_FOLDER: &str = "jellyfin-radio-interstitials";
+/// Tags marking a clip as a jingle, compared case insensitively.
+const JINGLE_TAGS: [&str; 2] = ["jingle", "station-id"];
+
+/// Where interstitials are kept in Jellyfin.
+pub enum Source {
+    Collection(String),
+    Playlist(String),
+}
+
+/// Mirror interstitials from Jellyfin into `folder`, in the same layout as
+/// `INTERSTITIAL_PATH`, so they are scheduled like local files. `folder` is
+/// wiped on start, so it has to be our own `CACHE_FOLDER`.
+///
+/// Clips named or tagged with a time (`07:30 Good morning`, `time:07:30`)
+/// go to the time folder, clips tagged `jingle` or `station-id` to the
+/// jingle folder. Everything else is ignored.
+pub async fn run(client: Arc<JellyfinClient>, user_id: String, source: Source, folder: PathBuf) {
+    let parent_id = loop {
+        match find_parent(&client, &user_id, &source).await {
+            Ok(parent_id) => break parent_id,
+            Err(e) => {
+                tracing::error!("Error looking up interstitials in Jellyfin: {}", e);
+                tokio::time::sleep(SYNC_INTERVAL).await;
+            }
+        }
+    };
+
+    // Start from scratch, the folder is ours and only ever holds our copies
+    debug_assert!(folder.ends_with(CACHE_FOLDER));
+    let _ = std::fs::remove_dir_all(&folder);
+    for subfolder in [TIME_FOLDER, JINGLE_FOLDER] {
+        if let Err(e) = std::fs::create_dir_all(folder.join(subfolder)) {
+            tracing::error!("Can't create interstitial cache at {:?}: {}", folder, e);
+            return;
+        }
+    }
+
+    let mut synced = HashMap::new();
+    loop {
+        if let Err(e) = sync(&client, &user_id, &parent_id, &folder, &mut synced).await {
+            tracing::warn!("Error syncing interstitials from Jellyfin: {}", e);
+        }
+        tokio::time::sleep(SYNC_INTERVAL).await;
+    }
+}
+
+async fn find_parent(
+    client: &JellyfinClient,
+    user_id: &str,
+    source: &Source,
+) -> anyhow::Result<String> {
+    match source {
+        Source::Collection(name) => client
+            .views(user_id)
+            .await?
+            .into_iter()
+            .find(|c| &c.name == name)
+            .map(|c| c.id)
+            .ok_or(anyhow::anyhow!("Collection {name} not found")),
+        Source::Playlist(name) => Ok(client
+            .find_item(user_id, "", ItemKind::Playlist, name)
+            .await?
+            .id),
+    }
+}
+
+/// A downloaded clip, by item id.
+struct Synced {
+    etag: Option<String>,
+    path: PathBuf,
+}
+
+async fn sync(
+    client: &JellyfinClient,
+    user_id: &str,
+    parent_id: &str,
+    folder: &Path,
+    synced: &mut HashMap<String, Synced>,
+) -> anyhow::Result<()> {
+    let clips = client.clips(user_id, parent_id).await?;
+
+    let mut wanted = HashMap::new();
+    for clip in clips {
+        let Some(place) = place(&clip) else {
+            tracing::debug!("Clip {} has no time or jingle tag, ignoring", clip.name);
+            continue;
+        };
+        wanted.insert(clip.id.clone(), (clip, place));
+    }
+
+    synced.retain(|id, file| {
+        let keep = wanted.get(id).is_some_and(|(clip, place)| {
+            clip.etag == file.etag && file.path.with_extension("") == folder.join(place)
+        });
+        if !keep {
+            tracing::info!("Removing interstitial {:?}", file.path);
+            let _ = std::fs::remove_file(&file.path);
+        }
+        keep
+    });
+
+    for (id, (clip, place)) in wanted {
+        if synced.contains_key(&id) {
+            continue;
+        }
+        tracing::info!("Downloading interstitial {}", clip.name);
+        let (extension, body) = match client.download(&id).await {
+            Ok(download) => download,
+            Err(e) => {
+                tracing::warn!("Error downloading interstitial {}: {}", clip.name, e);
+                continue;
+            }
+        };
+        let mut path = folder.join(place);
+        if let Some(extension) = extension {
+            path.set_extension(extension);
+        }
+        // Write outside the scheduled folders first, so a reload never sees
+        // half a file
+        let partial = folder.join(format!("{id}.part"));
+        tokio::fs::write(&partial, body).await?;
+        tokio::fs::rename(&partial, &path).await?;
+        synced.insert(
+            id,
+            Synced {
+                etag: clip.etag,
+                path,
+            },
+        );
+    }
+    Ok(())
+}
+
+/// Where a clip goes, relative to the cache folder and without extension.
+fn place(clip: &Clip) -> Option<PathBuf> {
+    let time = clip
+        .tags
+        .iter()
+        .find_map(|tag| parse_time(tag.strip_prefix("time:").unwrap_or(tag)))
+        .or_else(|| parse_time(&clip.name));
+    if let Some((hour, minute)) = time {
+        return Some(Path::new(TIME_FOLDER).join(format!("{hour:02}_{minute:02}_{}", clip.id)));
+    }
+    let jingle = clip
+        .tags
+        .iter()
+        .any(|tag| JINGLE_TAGS.iter().any(|j| tag.eq_ignore_ascii_case(j)));
+    jingle.then(|| Path::new(JINGLE_FOLDER).join(&clip.id))
+}
+
+/// Parse a leading `HH:MM` or `HH_MM`.
+fn parse_time(text: &str) -> Option<(u32, u32)> {
+    let text = text.trim().get(..5)?.replace('_', ":");
+    let time = chrono::NaiveTime::parse_from_str(&text, "%H:%M").ok()?;
+    Some((time.hour(), time.minute()))
+}
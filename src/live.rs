@@ -0,0 +1,85 @@
+use std::collections::VecDeque;
+use std::sync::mpsc;
+use std::time::Duration;
+
+use awedio::{NextSample, Sound};
+
+/// Interleaved 16 bit PCM received while it plays. Waits until `prebuffer`
+/// worth of audio arrived before starting, pauses while no samples are
+/// buffered and finishes once the sender is dropped.
+pub struct LiveInput {
+    receiver: mpsc::Receiver<Vec<i16>>,
+    buffer: VecDeque<i16>,
+    sample_rate: u32,
+    channel_count: u16,
+    /// Samples to collect before starting.
+    prebuffer: usize,
+    started: bool,
+    disconnected: bool,
+}
+
+impl LiveInput {
+    pub fn new(
+        sample_rate: u32,
+        channel_count: u16,
+        prebuffer: Duration,
+    ) -> (Self, mpsc::Sender<Vec<i16>>) {
+        let (sender, receiver) = mpsc::channel();
+        let prebuffer =
+            (prebuffer.as_secs_f64() * sample_rate as f64) as usize * channel_count as usize;
+        (
+            Self {
+                receiver,
+                buffer: VecDeque::new(),
+                sample_rate,
+                channel_count,
+                prebuffer,
+                started: false,
+                disconnected: false,
+            },
+            sender,
+        )
+    }
+
+    fn receive(&mut self) {
+        loop {
+            match self.receiver.try_recv() {
+                Ok(samples) => self.buffer.extend(samples),
+                Err(mpsc::TryRecvError::Empty) => break,
+                Err(mpsc::TryRecvError::Disconnected) => {
+                    self.disconnected = true;
+                    break;
+                }
+            }
+        }
+    }
+}
+
+impl Sound for LiveInput {
+    fn channel_count(&self) -> u16 {
+        self.channel_count
+    }
+
+    fn sample_rate(&self) -> u32 {
+        self.sample_rate
+    }
+
+    fn next_sample(&mut self) -> Result<NextSample, awedio::Error> {
+        if self.buffer.is_empty() || !self.started {
+            self.receive();
+        }
+        if !self.started {
+            if self.buffer.len() < self.prebuffer && !self.disconnected {
+                return Ok(NextSample::Paused);
+            }
+            self.started = true;
+        }
+        match self.buffer.pop_front() {
+            Some(sample) => Ok(NextSample::Sample(sample)),
+            None if self.disconnected => Ok(NextSample::Finished),
+            None => Ok(NextSample::Paused),
+        }
+    }
+
+    fn on_start_of_batch(&mut self) {}
+}
@@ -2,13 +2,36 @@ use awedio::Sound;
 use envconfig::Envconfig;
 use hyper::server::conn::http1;
 use hyper_util::rt::TokioIo;
-use std::{net::SocketAddr, time::Duration};
+use std::sync::atomic::AtomicBool;
+use std::{net::SocketAddr, sync::Arc, time::Duration};
 use tokio::net::TcpListener;
 use tracing_subscriber::fmt::format::FmtSpan;
 
+mod announce;
+mod ducking;
+mod events;
+mod icecast;
+mod interstitial;
 mod jellyfin;
+mod jingles;
+mod library;
+mod live;
+mod nowplaying;
+mod pcm;
 mod player;
+mod playlist;
+mod renderers;
+mod reporting;
+mod rotation;
+mod rtp;
+mod scrobbler;
+mod server;
+mod shows;
+mod source;
+mod status;
 mod streamer;
+mod tts;
+mod upnp;
 
 #[derive(Envconfig, Clone)]
 struct Config {
@@ -16,7 +39,25 @@ struct Config {
     pub jellyfin_url: String,
 
     #[envconfig(from = "JELLYFIN_API_KEY")]
-    pub jellyfin_api_key: String,
+    pub jellyfin_api_key: Option<String>,
+
+    #[envconfig(from = "JELLYFIN_USER")]
+    pub jellyfin_user: Option<String>,
+
+    #[envconfig(from = "JELLYFIN_PASSWORD")]
+    pub jellyfin_password: Option<String>,
+
+    #[envconfig(from = "JELLYFIN_QUICK_CONNECT", default = "false")]
+    pub jellyfin_quick_connect: bool,
+
+    #[envconfig(from = "JELLYFIN_DEVICE_ID")]
+    pub jellyfin_device_id: Option<String>,
+
+    #[envconfig(from = "JELLYFIN_TOKEN_FILE")]
+    pub jellyfin_token_file: Option<String>,
+
+    #[envconfig(from = "JELLYFIN_REPORT_PLAYBACK", default = "true")]
+    pub jellyfin_report_playback: bool,
 
     #[envconfig(from = "JELLYFIN_COLLECTION_NAME")]
     pub jellyfin_collection_name: String,
@@ -27,58 +68,127 @@ struct Config {
     #[envconfig(from = "HOST", default = "0.0.0.0")]
     pub host: String,
 
+    #[envconfig(from = "STATION_NAME", default = "Jellyfin Radio")]
+    pub station_name: String,
+
+    #[envconfig(from = "ANNOUNCE_TOKEN")]
+    pub announce_token: Option<String>,
+
+    #[envconfig(from = "SOURCE_PORT")]
+    pub source_port: Option<u16>,
+
+    #[envconfig(from = "SOURCE_USER", default = "source")]
+    pub source_user: String,
+
+    #[envconfig(from = "SOURCE_PASSWORD")]
+    pub source_password: Option<String>,
+
+    #[envconfig(from = "ICECAST_URL")]
+    pub icecast_url: Option<String>,
+
+    #[envconfig(from = "ICECAST_USER", default = "source")]
+    pub icecast_user: String,
+
+    #[envconfig(from = "ICECAST_PASSWORD")]
+    pub icecast_password: Option<String>,
+
+    #[envconfig(from = "ICECAST_NAME", default = "jellyfin-radio")]
+    pub icecast_name: String,
+
+    #[envconfig(from = "ICECAST_DESCRIPTION")]
+    pub icecast_description: Option<String>,
+
+    #[envconfig(from = "ICECAST_GENRE")]
+    pub icecast_genre: Option<String>,
+
+    #[envconfig(from = "ICECAST_PUBLIC", default = "false")]
+    pub icecast_public: bool,
+
+    #[envconfig(from = "PCM_FIFO")]
+    pub pcm_fifo: Option<String>,
+
+    #[envconfig(from = "PCM_TCP")]
+    pub pcm_tcp: Option<String>,
+
+    #[envconfig(from = "PCM_TCP_PORT")]
+    pub pcm_tcp_port: Option<u16>,
+
+    #[envconfig(from = "RTP_ADDRESS")]
+    pub rtp_address: Option<SocketAddr>,
+
+    #[envconfig(from = "RTP_TTL", default = "1")]
+    pub rtp_ttl: u32,
+
+    #[envconfig(from = "RTP_PTIME", default = "5")]
+    pub rtp_ptime: f64,
+
+    #[envconfig(from = "UPNP", default = "false")]
+    pub upnp: bool,
+
+    #[envconfig(from = "UPNP_NAME")]
+    pub upnp_name: Option<String>,
+
+    #[envconfig(from = "RENDERERS_PATH")]
+    pub renderers_path: Option<String>,
+
+    #[envconfig(from = "CONTROL_TOKEN")]
+    pub control_token: Option<String>,
+
     #[envconfig(from = "SONG_PREFETCH", default = "2")]
     pub song_prefetch: u32,
 
     #[envconfig(from = "INTERSTITIAL_PATH")]
     pub interstitial_path: Option<String>,
-}
 
-async fn get_time_file_map(
-    folder: &std::path::Path,
-) -> std::collections::HashMap<chrono::NaiveTime, Vec<std::path::PathBuf>> {
-    // time announce logic
-    let time_files: Vec<std::path::PathBuf> = std::fs::read_dir(folder)
-        .unwrap()
-        .filter_map(|v| v.ok())
-        .filter(|v| !v.path().is_dir())
-        .map(|v| v.path().clone())
-        .collect();
-
-    let mut time_map = std::collections::HashMap::new();
-    for path in time_files.iter() {
-        if let Ok((time, path)) = async {
-            let mut name_split = path
-                .file_stem()
-                .ok_or(anyhow::anyhow!("Wrong file stem!"))?
-                .to_str()
-                .ok_or(anyhow::anyhow!("Wrong file path!"))?
-                .split("_");
-            let hour = name_split
-                .next()
-                .ok_or(anyhow::anyhow!("No hour!"))?
-                .parse()?;
-            let minute = name_split
-                .next()
-                .ok_or(anyhow::anyhow!("No minute!"))?
-                .parse()?;
-            let time = chrono::NaiveTime::from_hms_opt(hour, minute, 0)
-                .ok_or(anyhow::anyhow!("Can't parse time"))?;
-            Ok::<_, anyhow::Error>((time, path))
-        }
-        .await
-        {
-            time_map
-                .entry(time)
-                .or_insert_with(Vec::new)
-                .push(path.clone());
-        }
-    }
-    time_map
+    #[envconfig(from = "INTERSTITIAL_COLLECTION")]
+    pub interstitial_collection: Option<String>,
+
+    #[envconfig(from = "INTERSTITIAL_PLAYLIST")]
+    pub interstitial_playlist: Option<String>,
+
+    #[envconfig(from = "INTERSTITIAL_CACHE_PATH")]
+    pub interstitial_cache_path: Option<String>,
+
+    #[envconfig(from = "INTERSTITIAL_MODE", default = "duck")]
+    pub interstitial_mode: interstitial::Mode,
+
+    #[envconfig(from = "INTERSTITIAL_TOLERANCE", default = "180")]
+    pub interstitial_tolerance: u64,
+
+    #[envconfig(from = "JINGLE_EVERY_SONGS")]
+    pub jingle_every_songs: Option<u32>,
+
+    #[envconfig(from = "JINGLE_EVERY_MINUTES")]
+    pub jingle_every_minutes: Option<u64>,
+
+    #[envconfig(from = "SHOWS_PATH")]
+    pub shows_path: Option<String>,
+
+    #[envconfig(from = "TTS_COMMAND")]
+    pub tts_command: Option<String>,
+
+    #[envconfig(from = "TTS_EVERY_SONGS", default = "3")]
+    pub tts_every_songs: u32,
+
+    #[envconfig(from = "LISTENBRAINZ_URL", default = "https://api.listenbrainz.org")]
+    pub listenbrainz_url: String,
+
+    #[envconfig(from = "LISTENBRAINZ_TOKEN")]
+    pub listenbrainz_token: Option<String>,
+
+    #[envconfig(from = "SCROBBLE_QUEUE_FILE")]
+    pub scrobble_queue_file: Option<String>,
+
+    #[envconfig(from = "INSTANT_MIX_SEED")]
+    pub instant_mix_seed: Option<String>,
+
+    #[envconfig(from = "INSTANT_MIX_SIZE", default = "50")]
+    pub instant_mix_size: u32,
 }
 
 #[tokio::main]
 async fn main() -> anyhow::Result<()> {
+    let started = chrono::Local::now();
     let filter = std::env::var("RUST_LOG")
         .unwrap_or_else(|_| "jellyfin_radio=debug,tracing=info,hyper=info".to_owned());
     tracing_subscriber::fmt()
@@ -87,40 +197,151 @@ async fn main() -> anyhow::Result<()> {
         .init();
     let config = Config::init_from_env().unwrap();
 
-    let client =
-        jellyfin::JellyfinClient::new(config.jellyfin_url.into(), config.jellyfin_api_key.into());
+    let credentials = if let Some(password) = config.jellyfin_password.clone() {
+        jellyfin::Credentials::Password {
+            username: config.jellyfin_user.clone().ok_or(anyhow::anyhow!(
+                "JELLYFIN_PASSWORD requires JELLYFIN_USER to be set!"
+            ))?,
+            password,
+        }
+    } else if config.jellyfin_quick_connect {
+        jellyfin::Credentials::QuickConnect
+    } else {
+        jellyfin::Credentials::ApiKey(config.jellyfin_api_key.clone().ok_or(anyhow::anyhow!(
+            "Specify JELLYFIN_API_KEY, JELLYFIN_PASSWORD or JELLYFIN_QUICK_CONNECT!"
+        ))?)
+    };
+    let device_id = config
+        .jellyfin_device_id
+        .clone()
+        .or_else(|| std::env::var("HOSTNAME").ok())
+        .unwrap_or_else(|| "jellyfin-radio".to_owned());
+    let client = Arc::new(jellyfin::JellyfinClient::new(
+        config.jellyfin_url.clone(),
+        credentials,
+        device_id.clone(),
+        config.jellyfin_token_file.clone().map(Into::into),
+    ));
+
+    let user = if client.is_user_session() {
+        // Regular users can't list other users, the logged in user is the one to play as
+        client.me().await?
+    } else {
+        let users = client.users().await?;
+        match &config.jellyfin_user {
+            Some(name) => users
+                .into_iter()
+                .find(|u| u.name == *name || u.id == *name)
+                .ok_or(anyhow::anyhow!("User {name} not found!"))?,
+            None => users
+                .into_iter()
+                .find(|u| u.policy.is_administrator)
+                .ok_or(anyhow::anyhow!(
+                    "No Admin user found! Specify a user with JELLYFIN_USER."
+                ))?,
+        }
+    };
+    tracing::info!("Playing as user {}", user.name);
 
-    let admin_user = client
-        .users()
-        .await?
-        .into_iter()
-        .filter(|u| u.policy.is_administrator)
-        .next()
-        .expect("No Admin user found!");
     let matched_collection = client
-        .views(&admin_user.id)
+        .views(&user.id)
         .await?
         .into_iter()
-        .filter(|c| c.name == config.jellyfin_collection_name)
-        .next()
-        .expect("Collection not found!");
+        .find(|c| c.name == config.jellyfin_collection_name)
+        .ok_or(anyhow::anyhow!(
+            "Collection {} not found for user {}!",
+            config.jellyfin_collection_name,
+            user.name
+        ))?;
+    user.validate(&matched_collection.id)?;
+
+    let mut rotation = match config.instant_mix_seed {
+        Some(seed) => {
+            tracing::info!("Playing instant mixes seeded from {}", seed);
+            rotation::Rotation::instant_mix(
+                user.id.clone(),
+                matched_collection.id.clone(),
+                seed.parse()?,
+                config.instant_mix_size,
+            )
+        }
+        None => rotation::Rotation::random(user.id.clone(), matched_collection.id.clone()),
+    };
+
+    let on_air = shows::OnAir::default();
+    // Whether a live source took over, only announcements may talk over it
+    let live_on_air = Arc::new(AtomicBool::new(false));
 
     let addr: SocketAddr = SocketAddr::from((
         config.host.parse::<std::net::Ipv4Addr>().unwrap(),
         config.port,
     ));
 
-    let (streamer_backend, mut streamer_manager) = streamer::StreamerBackend::start()?;
+    let events = events::EventBus::default();
+    let (streamer_backend, mut streamer_manager) =
+        streamer::StreamerBackend::start(events.clone())?;
 
     let (mixer, mixer_controller) = awedio::sounds::SoundMixer::new(2, 48_000).controllable();
     // basic playlist playback
 
     let (player, mut player_controller) = player::Player::new(config.song_prefetch);
-    let player = Box::new(player);
+    let (player, ducker_controller) = ducking::Ducker::new(Box::new(player));
 
     let mut player_mixer_controller = mixer_controller.clone();
-    player_mixer_controller.add(player);
-    let mut announce_downmix_player_controller = player_controller.clone();
+    player_mixer_controller.add(Box::new(player));
+
+    // Live sources get their own ducker, so announcements can talk over them
+    let (live_mixer, live_mixer_controller) =
+        awedio::sounds::SoundMixer::new(2, 48_000).controllable();
+    let (live_mixer, live_ducker_controller) = ducking::Ducker::new(Box::new(live_mixer));
+    player_mixer_controller.add(Box::new(live_mixer));
+    let announce_downmix_player_controller = player_controller.clone();
+    let source_player_controller = player_controller.clone();
+
+    if config.jellyfin_report_playback {
+        tokio::task::spawn(reporting::report_playback(
+            client.clone(),
+            user.id.clone(),
+            player_controller.clone(),
+        ));
+    }
+
+    tokio::task::spawn(events::forward_player_events(
+        events.clone(),
+        player_controller.subscribe(),
+    ));
+
+    let (now_playing_sender, now_playing) = tokio::sync::watch::channel(Default::default());
+    tokio::task::spawn(nowplaying::run(
+        now_playing_sender,
+        player_controller.clone(),
+    ));
+
+    if let Some(token) = config.listenbrainz_token.clone() {
+        tracing::info!("Submitting listens to {}", config.listenbrainz_url);
+        let scrobbler = scrobbler::Scrobbler::new(
+            config.listenbrainz_url.clone(),
+            token,
+            config.scrobble_queue_file.clone().map(Into::into),
+        );
+        tokio::task::spawn(scrobbler.run(player_controller.subscribe()));
+    }
+
+    if let Some(shows_path) = config.shows_path.clone() {
+        let shows = shows::load(shows_path.as_ref())?;
+        tracing::info!("Loaded {} shows from {}", shows.len(), shows_path);
+        tokio::task::spawn(shows::run(
+            shows,
+            client.clone(),
+            user.id.clone(),
+            matched_collection.id.clone(),
+            on_air.clone(),
+            announce_downmix_player_controller.clone(),
+        ));
+    }
+
+    let song_client = client.clone();
+    let song_events = events.clone();
     tokio::task::spawn(async move {
         loop {
             player_controller.wait_for_queue().await;
@@ -129,22 +350,31 @@ async fn main() -> anyhow::Result<()> {
 
             loop {
                 let result = async {
-                    let item = client
-                        .random_audio(&admin_user.id, &matched_collection.id)
-                        .await?;
+                    let generation = on_air.generation();
+                    let item = match on_air.next_song() {
+                        Some(item) => item,
+                        None => rotation.next(&song_client).await?,
+                    };
 
                     tracing::info!("Fetching {} - {}", item.artists.join(","), item.name);
-                    let sound = client.fetch_audio(item).await?;
+                    let sound = song_client.fetch_audio(&item).await?;
                     tracing::info!("Fetched Song!");
                     if sound.channel_count() > 2 {
                         anyhow::bail!("Too many channels, skipping!");
                     }
-                    player_controller.add(Box::new(sound));
+                    let name = item.name.clone();
+                    let queued = on_air.queue_unless_started(generation, || {
+                        player_controller.add(Box::new(sound), player::Track::Song(item))
+                    });
+                    if !queued {
+                        tracing::info!("A show started, dropping {}", name);
+                    }
                     anyhow::Ok(())
                 }
                 .await;
                 if let Err(e) = result {
                     tracing::error!("Error fetching new song: {}", e);
+                    song_events.source_error("jellyfin", &e);
                     tokio::time::sleep(Duration::from_secs(1)).await;
                 } else {
                     break;
@@ -153,112 +383,242 @@ async fn main() -> anyhow::Result<()> {
         }
     });
 
-    let mut time_announce_mixer_controller = mixer_controller.clone();
+    if let Some(tts_command) = config.tts_command.clone() {
+        let speaker = tts::Speaker::new(&tts_command)?;
+        tracing::info!("Announcing songs every {} songs", config.tts_every_songs);
+        tokio::task::spawn(tts::run(
+            speaker,
+            config.tts_every_songs,
+            announce_downmix_player_controller.clone(),
+            live_on_air.clone(),
+        ));
+    }
 
-    tokio::task::spawn(async move {
-        if config.interstitial_path.is_none() {
-            tracing::info!("No interstitials, skipping interstitial task. Specify a folder with INTERSTITIAL_PATH.");
-            return;
+    let mut interstitial_folders: Vec<std::path::PathBuf> =
+        config.interstitial_path.iter().map(Into::into).collect();
+    let library_source = match (
+        config.interstitial_collection.clone(),
+        config.interstitial_playlist.clone(),
+    ) {
+        (Some(collection), None) => Some(library::Source::Collection(collection)),
+        (None, Some(playlist)) => Some(library::Source::Playlist(playlist)),
+        (None, None) => None,
+        (Some(_), Some(_)) => {
+            anyhow::bail!("Specify only one of INTERSTITIAL_COLLECTION and INTERSTITIAL_PLAYLIST!")
         }
+    };
+    if let Some(source) = library_source {
+        let cache_path = config
+            .interstitial_cache_path
+            .clone()
+            .map(std::path::PathBuf::from)
+            .unwrap_or_else(std::env::temp_dir)
+            .join(library::CACHE_FOLDER);
+        tracing::info!("Syncing interstitials from Jellyfin to {:?}", cache_path);
+        tokio::task::spawn(library::run(
+            client.clone(),
+            user.id.clone(),
+            source,
+            cache_path.clone(),
+        ));
+        interstitial_folders.push(cache_path);
+    }
 
-        let mut time_file_path = std::path::PathBuf::from(config.interstitial_path.unwrap());
-        time_file_path.push("time");
-        tracing::info!("Looking for time files at {:?}", time_file_path);
-
-        let time_file_map = get_time_file_map(&time_file_path).await;
-        loop {
-            let fade_duration = Duration::from_secs(2);
-            let fade_steps = 100;
-            let fade_minimum_level = 0.1;
-
-            let fade_steps_max = fade_steps;
-            let fade_steps_min = (fade_minimum_level * fade_steps as f32) as u32;
-
-            let now = chrono::Local::now();
-            use itertools::Itertools;
-            let next_time = if let Some(next_time) = time_file_map
-                .keys()
-                .sorted()
-                .filter(|k| **k > now.time())
-                .next()
-            {
-                Some(next_time)
-            } else {
-                time_file_map.keys().sorted().next()
-            };
-
-            if let Some(next_time) = next_time {
-                let paths = time_file_map.get(next_time).unwrap(); // definitely exists, we just did the math
-                use rand::seq::SliceRandom;
-                let next_path = paths.choose(&mut rand::thread_rng());
-                if next_path.is_none() {
-                    continue;
-                }
-                let next_path = next_path.unwrap();
-
-                let interstitial_time = if *next_time > now.time() {
-                    now.date_naive()
-                        .and_time(*next_time)
-                        .and_local_timezone(chrono::Local)
-                } else {
-                    now.date_naive()
-                        .checked_add_days(chrono::Days::new(1))
-                        .unwrap()
-                        .and_time(*next_time)
-                        .and_local_timezone(chrono::Local)
-                }
-                .unwrap();
+    if !interstitial_folders.is_empty() {
+        if config.jingle_every_songs.is_some() || config.jingle_every_minutes.is_some() {
+            let jingle_folders: Vec<_> = interstitial_folders
+                .iter()
+                .map(|folder| folder.join(jingles::JINGLE_FOLDER))
+                .collect();
+            tracing::info!("Playing jingles from {:?}", jingle_folders);
+            tokio::task::spawn(jingles::run(
+                jingle_folders,
+                jingles::Rotation {
+                    every_songs: config.jingle_every_songs,
+                    every: config
+                        .jingle_every_minutes
+                        .map(|minutes| Duration::from_secs(minutes * 60)),
+                },
+                announce_downmix_player_controller.clone(),
+                live_on_air.clone(),
+            ));
+        }
 
-                tracing::info!(
-                    "Next Internstitial time {interstitial_time}: {:?}",
-                    next_path
-                );
+        tokio::task::spawn(interstitial::run(
+            interstitial_folders,
+            config.interstitial_mode,
+            Duration::from_secs(config.interstitial_tolerance),
+            mixer_controller.clone(),
+            ducker_controller.clone(),
+            announce_downmix_player_controller,
+            events.clone(),
+            live_on_air.clone(),
+        ));
+    } else {
+        tracing::info!("No interstitials, skipping interstitial task. Specify a folder with INTERSTITIAL_PATH or a Jellyfin collection with INTERSTITIAL_COLLECTION.");
+    }
 
-                tokio::time::sleep_until(
-                    tokio::time::Instant::now() + (interstitial_time - now).to_std().unwrap(),
-                )
-                .await;
+    streamer_manager.play(Box::new(mixer));
 
-                tracing::info!("Playing interstitial {:?}", next_path);
+    if let Some(source_port) = config.source_port {
+        let credentials = source::Credentials {
+            user: config.source_user.clone(),
+            password: config.source_password.clone().ok_or(anyhow::anyhow!(
+                "SOURCE_PORT requires SOURCE_PASSWORD to be set!"
+            ))?,
+        };
+        let source_addr = SocketAddr::from((addr.ip(), source_port));
+        let live_mixer_controller = live_mixer_controller.clone();
+        let ducker_controller = ducker_controller.clone();
+        let player_controller = source_player_controller.clone();
+        let events = events.clone();
+        let live_on_air = live_on_air.clone();
+        tokio::task::spawn(async move {
+            if let Err(e) = source::run(
+                source_addr,
+                credentials,
+                live_mixer_controller,
+                ducker_controller,
+                player_controller,
+                events,
+                live_on_air,
+            )
+            .await
+            {
+                tracing::error!("Live source input failed: {}", e);
+            }
+        });
+    }
 
-                if let Ok(sound) =
-                    tokio::task::block_in_place(|| awedio::sounds::open_file(next_path.as_path()))
-                {
-                    let (sound, completion_notifier) = sound.with_async_completion_notifier();
-                    for v in (fade_steps_min..=fade_steps_max).rev() {
-                        let volume = v as f32 / fade_steps as f32;
-                        announce_downmix_player_controller.set_volume(volume);
-                        tokio::time::sleep(fade_duration / (fade_steps_max - fade_steps_min)).await;
-                    }
+    if let Some(icecast_url) = config.icecast_url.clone() {
+        let output = icecast::Output {
+            url: icecast_url.parse()?,
+            user: config.icecast_user.clone(),
+            password: config.icecast_password.clone().ok_or(anyhow::anyhow!(
+                "ICECAST_URL requires ICECAST_PASSWORD to be set!"
+            ))?,
+            name: config.icecast_name.clone(),
+            description: config.icecast_description.clone(),
+            genre: config.icecast_genre.clone(),
+            public: config.icecast_public,
+        };
+        tokio::task::spawn(icecast::run(
+            output,
+            streamer_backend.clone(),
+            source_player_controller.subscribe(),
+        ));
+    }
 
-                    time_announce_mixer_controller.add(Box::new(sound));
-                    let _ = completion_notifier.await;
+    if let Some(pcm_fifo) = config.pcm_fifo.clone() {
+        tokio::task::spawn(pcm::fifo(pcm_fifo.into(), streamer_backend.clone()));
+    }
+    if let Some(pcm_tcp) = config.pcm_tcp.clone() {
+        tokio::task::spawn(pcm::connect(pcm_tcp, streamer_backend.clone()));
+    }
+    if let Some(pcm_tcp_port) = config.pcm_tcp_port {
+        let pcm_addr = SocketAddr::from((addr.ip(), pcm_tcp_port));
+        let streamer_backend = streamer_backend.clone();
+        tokio::task::spawn(async move {
+            if let Err(e) = pcm::listen(pcm_addr, streamer_backend).await {
+                tracing::error!("PCM output failed: {}", e);
+            }
+        });
+    }
 
-                    for v in fade_steps_min..=fade_steps_max {
-                        let volume = v as f32 / fade_steps as f32;
-                        announce_downmix_player_controller.set_volume(volume);
-                        tokio::time::sleep(fade_duration / (fade_steps_max - fade_steps_min)).await;
-                    }
-                } else {
-                    tracing::error!("Error playing interstitial");
+    let sdp = match config.rtp_address {
+        Some(rtp_address) => {
+            let ptime = Duration::try_from_secs_f64(config.rtp_ptime / 1000.0)
+                .ok()
+                .filter(|ptime| !ptime.is_zero())
+                .ok_or(anyhow::anyhow!(
+                    "RTP_PTIME must be a positive number of milliseconds, got {}",
+                    config.rtp_ptime
+                ))?;
+            let output = rtp::Output::bind(rtp_address, config.rtp_ttl, ptime).await?;
+            let sdp = output.sdp("jellyfin-radio")?;
+            tokio::task::spawn(output.run(streamer_backend.clone()));
+            Some(sdp)
+        }
+        None => None,
+    };
+
+    let announcer = config.announce_token.clone().map(|token| {
+        tracing::info!("Accepting announcements at /announce");
+        announce::Announcer::new(
+            token,
+            mixer_controller.clone(),
+            ducker_controller.clone(),
+            live_ducker_controller.clone(),
+        )
+    });
+    let upnp = config.upnp.then(|| {
+        let media_server = upnp::MediaServer::new(
+            config
+                .upnp_name
+                .clone()
+                .unwrap_or_else(|| config.station_name.clone()),
+            &device_id,
+            addr.port(),
+        );
+        tokio::task::spawn({
+            let media_server = media_server.clone();
+            async move {
+                if let Err(e) = media_server.advertise().await {
+                    tracing::error!("UPnP advertisement failed: {}", e);
                 }
             }
-        }
+        });
+        media_server
     });
-
-    streamer_manager.play(Box::new(mixer));
+    let renderers = match config.renderers_path.clone() {
+        Some(renderers_path) => {
+            let renderers = renderers::Renderers::load(
+                renderers_path.as_ref(),
+                config.station_name.clone(),
+                addr.port(),
+            )?;
+            tracing::info!("Loaded renderers from {}", renderers_path);
+            tokio::task::spawn(renderers.clone().run());
+            Some(renderers)
+        }
+        None => None,
+    };
+    let server = server::Server {
+        streamer: streamer_backend,
+        announcer,
+        sdp,
+        upnp,
+        renderers,
+        control_token: config.control_token.clone(),
+        station_name: config.station_name.clone(),
+        now_playing,
+        client: client.clone(),
+        events,
+        status: status::Status {
+            name: config.station_name.clone(),
+            description: config.icecast_description.clone(),
+            genre: config.icecast_genre.clone(),
+            started,
+        },
+        peer: None,
+    };
 
     let listener = TcpListener::bind(addr).await?;
     tracing::info!("Listening on http://{}", addr);
     loop {
-        let (tcp, _) = listener.accept().await?;
+        let (tcp, peer) = listener.accept().await?;
         let io = TokioIo::new(tcp);
-        let backend = streamer_backend.clone();
+        let mut backend = server.clone();
+        backend.peer = Some(peer);
 
         tracing::debug!("New connection!");
 
         tokio::task::spawn(async move {
-            if let Err(err) = http1::Builder::new().serve_connection(io, backend).await {
+            if let Err(err) = http1::Builder::new()
+                .serve_connection(io, backend)
+                .with_upgrades()
+                .await
+            {
                 tracing::error!("Error serving connection: {:?}", err);
             }
         });
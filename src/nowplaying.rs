@@ -0,0 +1,104 @@
+use std::time::Duration;
+
+use bytes::Bytes;
+use futures_util::StreamExt;
+use http_body_util::combinators::BoxBody;
+use hyper::Response;
+use serde::Serialize;
+use tokio::sync::broadcast::error::RecvError;
+use tokio::sync::watch;
+use tokio_stream::wrappers::WatchStream;
+
+use crate::jellyfin::Audio;
+use crate::player::{PlayerController, PlayerEvent, Track};
+use crate::server::event_stream;
+
+/// Songs kept in the play history.
+const HISTORY_SIZE: usize = 10;
+/// Songs are queued without an event, so the queue is checked regularly.
+const QUEUE_REFRESH: Duration = Duration::from_secs(10);
+
+#[derive(Serialize, Clone, PartialEq)]
+pub struct Song {
+    pub id: String,
+    pub name: String,
+    pub artists: Vec<String>,
+    pub album: Option<String>,
+    /// Seconds.
+    pub duration: Option<f64>,
+    /// URL of the cover art, relative to the root of this server.
+    pub cover: String,
+    /// Item the cover art is taken from.
+    #[serde(skip)]
+    pub image_id: String,
+}
+
+impl From<&Audio> for Song {
+    fn from(audio: &Audio) -> Self {
+        // Songs rarely have their own image, their album usually has
+        let image_id = audio.album_id.as_ref().unwrap_or(&audio.id);
+        Self {
+            id: audio.id.clone(),
+            name: audio.name.clone(),
+            artists: audio.artists.clone(),
+            album: audio.album.clone(),
+            duration: audio.duration().map(|d| d.as_secs_f64()),
+            cover: format!("items/{image_id}/cover"),
+            image_id: image_id.clone(),
+        }
+    }
+}
+
+/// What's on air, what was and what will be.
+#[derive(Serialize, Clone, PartialEq, Default)]
+pub struct State {
+    pub current: Option<Song>,
+    /// Most recent first.
+    pub history: Vec<Song>,
+    pub upcoming: Vec<Song>,
+}
+
+/// Keep the state up to date with the player.
+pub async fn run(state: watch::Sender<State>, mut player_controller: PlayerController) {
+    let mut events = player_controller.subscribe();
+    let mut refresh = tokio::time::interval(QUEUE_REFRESH);
+    loop {
+        tokio::select! {
+            event = events.recv() => match event {
+                Ok(PlayerEvent::Started(Track::Song(audio))) => {
+                    state.send_modify(|state| {
+                        if let Some(previous) = state.current.replace(Song::from(&audio)) {
+                            state.history.insert(0, previous);
+                            state.history.truncate(HISTORY_SIZE);
+                        }
+                    });
+                }
+                Ok(_) | Err(RecvError::Lagged(_)) => continue,
+                Err(RecvError::Closed) => return,
+            },
+            _ = refresh.tick() => {}
+        }
+
+        let upcoming: Vec<Song> = player_controller
+            .upcoming_songs()
+            .await
+            .iter()
+            .map(Song::from)
+            .collect();
+        state.send_if_modified(|state| {
+            if state.upcoming == upcoming {
+                return false;
+            }
+            state.upcoming = upcoming;
+            true
+        });
+    }
+}
+
+/// Server-Sent Events with the whole state, whenever it changes.
+pub fn events(state: watch::Receiver<State>) -> Response<BoxBody<Bytes, anyhow::Error>> {
+    event_stream(WatchStream::new(state).map(|state| {
+        let json = serde_json::to_string(&state).unwrap_or_default();
+        format!("data: {json}\n\n")
+    }))
+}
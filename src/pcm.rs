@@ -0,0 +1,81 @@
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::time::Duration;
+
+use futures_util::StreamExt;
+use tokio::io::{AsyncWrite, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+
+use crate::streamer::{Chunk, StreamerBackend};
+
+const RETRY_INTERVAL: Duration = Duration::from_secs(5);
+
+/// The chunk as s16le, the sample format Snapcast expects by default.
+fn to_bytes(data: &Chunk) -> Vec<u8> {
+    data.iter().flat_map(|s| s.to_le_bytes()).collect()
+}
+
+/// Write raw PCM until writing fails.
+async fn write_pcm(
+    mut writer: impl AsyncWrite + Unpin,
+    streamer: &StreamerBackend,
+) -> std::io::Result<()> {
+    let mut pcm = streamer.subscribe();
+    while let Some(data) = pcm.next().await {
+        writer.write_all(&to_bytes(&data)).await?;
+    }
+    Ok(())
+}
+
+/// Write raw PCM to a named pipe, e.g. one read by Snapcast's `pipe`
+/// source. The pipe is not created, it has to exist already.
+pub async fn fifo(path: PathBuf, streamer: StreamerBackend) {
+    loop {
+        // Opening a pipe waits until it has a reader
+        let file = tokio::fs::OpenOptions::new().write(true).open(&path).await;
+        match file {
+            Ok(file) => {
+                tracing::info!("Writing PCM to {:?}", path);
+                if let Err(e) = write_pcm(file, &streamer).await {
+                    tracing::warn!("Writing PCM to {:?} failed: {}", path, e);
+                }
+            }
+            Err(e) => tracing::warn!("Can't open {:?} for PCM output: {}", path, e),
+        }
+        tokio::time::sleep(RETRY_INTERVAL).await;
+    }
+}
+
+/// Send raw PCM to a TCP server, e.g. Snapcast's `tcp` source in server
+/// mode, reconnecting whenever the connection is lost.
+pub async fn connect(addr: String, streamer: StreamerBackend) {
+    loop {
+        match TcpStream::connect(&addr).await {
+            Ok(tcp) => {
+                tracing::info!("Sending PCM to {}", addr);
+                if let Err(e) = write_pcm(tcp, &streamer).await {
+                    tracing::warn!("Sending PCM to {} failed: {}", addr, e);
+                }
+            }
+            Err(e) => tracing::warn!("Can't connect to {} for PCM output: {}", addr, e),
+        }
+        tokio::time::sleep(RETRY_INTERVAL).await;
+    }
+}
+
+/// Serve raw PCM to everyone connecting, e.g. Snapcast's `tcp` source in
+/// client mode.
+pub async fn listen(addr: SocketAddr, streamer: StreamerBackend) -> anyhow::Result<()> {
+    let listener = TcpListener::bind(addr).await?;
+    tracing::info!("Serving PCM on {}", addr);
+    loop {
+        let (tcp, peer) = listener.accept().await?;
+        tracing::info!("PCM client {} connected", peer);
+        let streamer = streamer.clone();
+        tokio::task::spawn(async move {
+            if let Err(e) = write_pcm(tcp, &streamer).await {
+                tracing::info!("PCM client {} disconnected: {}", peer, e);
+            }
+        });
+    }
+}
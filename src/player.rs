@@ -2,15 +2,49 @@ use awedio::NextSample;
 use awedio::Sound;
 use std::sync::mpsc;
 use std::sync::Arc;
-use tokio::sync::Notify;
+use std::time::Duration;
+use tokio::sync::{broadcast, oneshot, Notify};
+
+use crate::jellyfin::Audio;
 
 /// Heavily Based on awedios SoundList and Controllable implementations
 
 pub struct Player {
-    sounds: Vec<Box<dyn Sound>>,
+    sounds: Vec<Entry>,
     was_empty: bool,
     song_prefetch: u32,
-    volume_adjustment: f32,
+    /// Samples of the current track played so far, over all channels.
+    position: u64,
+    /// Hold the current track where it is, e.g. during a live show.
+    paused: bool,
+    events: broadcast::Sender<PlayerEvent>,
+}
+
+struct Entry {
+    sound: Box<dyn Sound>,
+    track: Track,
+}
+
+/// What a queued sound is.
+#[derive(Clone)]
+pub enum Track {
+    Song(Audio),
+    /// Announcements, jingles and the like, identified by their name.
+    Interstitial(String),
+}
+
+#[derive(Clone)]
+pub enum PlayerEvent {
+    /// The track played its first sample.
+    Started(Track),
+    /// The track played until its end.
+    Finished { track: Track, position: Duration },
+    /// The track was dropped because of an error.
+    Skipped { track: Track, position: Duration },
+    /// The player was paused in the middle of the track.
+    Paused { track: Track, position: Duration },
+    /// The player continues the track after a pause.
+    Resumed { track: Track, position: Duration },
 }
 
 type Command<S> = Box<dyn FnOnce(&mut S) + Send>;
@@ -25,16 +59,20 @@ pub struct PlayerControllable {
 pub struct PlayerController {
     command_sender: mpsc::Sender<Command<Player>>,
     queue_next_song_notify: Arc<Notify>,
+    events: broadcast::Sender<PlayerEvent>,
 }
 
 impl Player {
     /// Create a new empty Player.
     pub fn new(song_prefetch: u32) -> (PlayerControllable, PlayerController) {
+        let (events, _) = broadcast::channel(16);
         let inner = Player {
             sounds: Vec::new(),
             was_empty: false,
             song_prefetch,
-            volume_adjustment: 1.0,
+            position: 0,
+            paused: false,
+            events: events.clone(),
         };
 
         let queue_next_song_notify = Arc::new(tokio::sync::Notify::new());
@@ -49,25 +87,125 @@ impl Player {
         let controller = PlayerController {
             command_sender,
             queue_next_song_notify,
+            events,
         };
 
         (controllable, controller)
     }
 
     /// Add a Sound to be played after any existing sounds have `Finished`.
-    pub fn add(&mut self, sound: Box<dyn Sound>) {
+    pub fn add(&mut self, sound: Box<dyn Sound>, track: Track) {
         if self.sounds.is_empty() {
             self.was_empty = true;
         }
-        self.sounds.push(sound);
+        self.sounds.push(Entry { sound, track });
+    }
+
+    /// Add a Sound to be played right after the current one, behind any
+    /// other interstitials which are already waiting there.
+    pub fn insert_next(&mut self, sound: Box<dyn Sound>, track: Track) {
+        if self.sounds.is_empty() {
+            return self.add(sound, track);
+        }
+        let index = 1 + self.sounds[1..]
+            .iter()
+            .take_while(|e| matches!(e.track, Track::Interstitial(_)))
+            .count();
+        self.sounds.insert(index, Entry { sound, track });
+    }
+
+    /// Remove an interstitial which has not started playing yet.
+    fn remove_pending_interstitial(&mut self, name: &str) -> bool {
+        let Some(index) = self
+            .sounds
+            .iter()
+            .skip(1)
+            .position(|e| matches!(&e.track, Track::Interstitial(n) if n == name))
+        else {
+            return false;
+        };
+        self.sounds.remove(index + 1);
+        true
+    }
+
+    /// Whether the interstitial is queued after the current track.
+    fn has_pending_interstitial(&self, name: &str) -> bool {
+        self.sounds
+            .iter()
+            .skip(1)
+            .any(|e| matches!(&e.track, Track::Interstitial(n) if n == name))
+    }
+
+    /// Drop all songs queued after the current track, keeping interstitials.
+    fn clear_upcoming_songs(&mut self) {
+        if self.sounds.is_empty() {
+            return;
+        }
+        let mut index = 0;
+        self.sounds.retain(|e| {
+            index += 1;
+            index == 1 || matches!(e.track, Track::Interstitial(_))
+        });
+    }
+
+    /// The first song queued after the current track.
+    fn upcoming_song(&self) -> Option<Audio> {
+        self.sounds.iter().skip(1).find_map(|e| match &e.track {
+            Track::Song(audio) => Some(audio.clone()),
+            Track::Interstitial(_) => None,
+        })
     }
 
-    fn set_volume(&mut self, new: f32) {
-        self.volume_adjustment = new;
+    /// All songs queued after the current track.
+    fn upcoming_songs(&self) -> Vec<Audio> {
+        self.sounds
+            .iter()
+            .skip(1)
+            .filter_map(|e| match &e.track {
+                Track::Song(audio) => Some(audio.clone()),
+                Track::Interstitial(_) => None,
+            })
+            .collect()
     }
 
     fn should_prefetch(&self) -> bool {
-        self.sounds.len() <= self.song_prefetch as usize
+        let songs = self
+            .sounds
+            .iter()
+            .filter(|e| matches!(e.track, Track::Song(_)))
+            .count();
+        songs <= self.song_prefetch as usize
+    }
+
+    fn position(&self) -> Duration {
+        let Some(current) = self.sounds.first() else {
+            return Duration::ZERO;
+        };
+        let samples_per_second =
+            current.sound.sample_rate() as u64 * current.sound.channel_count() as u64;
+        Duration::from_secs_f64(self.position as f64 / samples_per_second.max(1) as f64)
+    }
+
+    fn set_paused(&mut self, paused: bool) {
+        if self.paused == paused {
+            return;
+        }
+        self.paused = paused;
+        let Some(current) = self.sounds.first() else {
+            return;
+        };
+        let track = current.track.clone();
+        let position = self.position();
+        self.emit(if paused {
+            PlayerEvent::Paused { track, position }
+        } else {
+            PlayerEvent::Resumed { track, position }
+        });
+    }
+
+    fn emit(&self, event: PlayerEvent) {
+        // Nobody listening is fine
+        let _ = self.events.send(event);
     }
 }
 
@@ -79,20 +217,20 @@ impl Sound for Player {
     fn channel_count(&self) -> u16 {
         self.sounds
             .first()
-            .map(|s| s.channel_count())
+            .map(|s| s.sound.channel_count())
             .unwrap_or(DEFAULT_CHANNEL_COUNT)
     }
 
     fn sample_rate(&self) -> u32 {
         self.sounds
             .first()
-            .map(|s| s.sample_rate())
+            .map(|s| s.sound.sample_rate())
             .unwrap_or(DEFAULT_SAMPLE_RATE)
     }
 
     fn on_start_of_batch(&mut self) {
-        for sound in &mut self.sounds {
-            sound.on_start_of_batch();
+        for entry in &mut self.sounds {
+            entry.sound.on_start_of_batch();
         }
     }
 
@@ -104,20 +242,40 @@ impl Sound for Player {
             self.was_empty = false;
             return Ok(NextSample::MetadataChanged);
         }
+        if self.paused {
+            return Ok(NextSample::Paused);
+        }
 
-        let next_sample = next_sound.next_sample();
+        let next_sample = next_sound.sound.next_sample();
         if let Err(e) = &next_sample {
             tracing::error!("Error playing track: {:?}", e);
         }
 
         let ret = match next_sample {
             Ok(NextSample::Sample(s)) => {
-                NextSample::Sample((s as f32 * self.volume_adjustment) as i16)
+                if self.position == 0 {
+                    let track = next_sound.track.clone();
+                    self.emit(PlayerEvent::Started(track));
+                }
+                self.position += 1;
+                NextSample::Sample(s)
             }
             Ok(NextSample::MetadataChanged | NextSample::Paused) => next_sample.unwrap(),
             Ok(NextSample::Finished) | Err(_) => {
                 // Just ignore the error
-                self.sounds.remove(0);
+                let position = self.position();
+                let finished = self.sounds.remove(0);
+                self.position = 0;
+                self.emit(match next_sample {
+                    Ok(_) => PlayerEvent::Finished {
+                        track: finished.track,
+                        position,
+                    },
+                    Err(_) => PlayerEvent::Skipped {
+                        track: finished.track,
+                        position,
+                    },
+                });
                 if self.sounds.is_empty() {
                     NextSample::Finished
                 } else {
@@ -202,20 +360,76 @@ impl Clone for PlayerController {
         Self {
             command_sender: self.command_sender.clone(),
             queue_next_song_notify: self.queue_next_song_notify.clone(),
+            events: self.events.clone(),
         }
     }
 }
 
 impl PlayerController {
-    pub fn add(&mut self, sound: Box<dyn Sound>) {
-        self.send_command(Box::new(|s: &mut Player| s.add(sound)));
+    pub fn add(&mut self, sound: Box<dyn Sound>, track: Track) {
+        self.send_command(Box::new(|s: &mut Player| s.add(sound, track)));
+    }
+
+    pub fn insert_next(&mut self, sound: Box<dyn Sound>, track: Track) {
+        self.send_command(Box::new(|s: &mut Player| s.insert_next(sound, track)));
+    }
+
+    /// Remove an interstitial which has not started playing yet. Returns
+    /// `false` if it was not found, because it is already playing or done.
+    pub async fn remove_pending_interstitial(&mut self, name: String) -> bool {
+        let (sender, receiver) = oneshot::channel();
+        self.send_command(Box::new(move |s: &mut Player| {
+            let _ = sender.send(s.remove_pending_interstitial(&name));
+        }));
+        receiver.await.unwrap_or(false)
     }
 
-    pub fn set_volume(&mut self, new: f32) {
-        self.send_command(Box::new(move |s: &mut Player| s.set_volume(new)));
+    pub async fn has_pending_interstitial(&mut self, name: String) -> bool {
+        let (sender, receiver) = oneshot::channel();
+        self.send_command(Box::new(move |s: &mut Player| {
+            let _ = sender.send(s.has_pending_interstitial(&name));
+        }));
+        receiver.await.unwrap_or(false)
+    }
+
+    pub fn set_paused(&mut self, paused: bool) {
+        self.send_command(Box::new(move |s: &mut Player| s.set_paused(paused)));
+    }
+
+    pub fn clear_upcoming_songs(&mut self) {
+        self.send_command(Box::new(|s: &mut Player| s.clear_upcoming_songs()));
+    }
+
+    pub async fn upcoming_song(&mut self) -> Option<Audio> {
+        let (sender, receiver) = oneshot::channel();
+        self.send_command(Box::new(move |s: &mut Player| {
+            let _ = sender.send(s.upcoming_song());
+        }));
+        receiver.await.ok().flatten()
+    }
+
+    pub async fn upcoming_songs(&mut self) -> Vec<Audio> {
+        let (sender, receiver) = oneshot::channel();
+        self.send_command(Box::new(move |s: &mut Player| {
+            let _ = sender.send(s.upcoming_songs());
+        }));
+        receiver.await.unwrap_or_default()
+    }
+
+    /// How far the current track has played.
+    pub async fn position(&mut self) -> Duration {
+        let (sender, receiver) = oneshot::channel();
+        self.send_command(Box::new(move |s: &mut Player| {
+            let _ = sender.send(s.position());
+        }));
+        receiver.await.unwrap_or_default()
     }
 
     pub async fn wait_for_queue(&mut self) {
         self.queue_next_song_notify.notified().await;
     }
+
+    pub fn subscribe(&self) -> broadcast::Receiver<PlayerEvent> {
+        self.events.subscribe()
+    }
 }
@@ -0,0 +1,124 @@
+use crate::upnp::escape;
+
+/// Playlist files pointing at the streams, for players which rather import
+/// those than a bare stream URL.
+#[derive(Clone, Copy)]
+pub enum Format {
+    M3u,
+    Pls,
+    Xspf,
+}
+
+impl Format {
+    pub fn from_path(path: &str) -> Option<Self> {
+        match path {
+            "/listen.m3u" => Some(Self::M3u),
+            "/listen.pls" => Some(Self::Pls),
+            "/listen.xspf" => Some(Self::Xspf),
+            _ => None,
+        }
+    }
+
+    pub fn content_type(self) -> &'static str {
+        match self {
+            Self::M3u => "audio/x-mpegurl",
+            Self::Pls => "audio/x-scpls",
+            Self::Xspf => "application/xspf+xml",
+        }
+    }
+
+    /// A playlist with an entry for each stream URL.
+    pub fn render(self, title: &str, urls: &[String]) -> String {
+        match self {
+            Self::M3u => {
+                let mut playlist = "#EXTM3U\n".to_owned();
+                for url in urls {
+                    playlist.push_str(&format!("#EXTINF:-1,{title}\n{url}\n"));
+                }
+                playlist
+            }
+            Self::Pls => {
+                let mut playlist = "[playlist]\n".to_owned();
+                for (i, url) in urls.iter().enumerate() {
+                    let n = i + 1;
+                    playlist.push_str(&format!("File{n}={url}\nTitle{n}={title}\nLength{n}=-1\n"));
+                }
+                playlist.push_str(&format!("NumberOfEntries={}\nVersion=2\n", urls.len()));
+                playlist
+            }
+            Self::Xspf => {
+                let tracks: String = urls
+                    .iter()
+                    .map(|url| {
+                        format!(
+                            "<track><location>{}</location><title>{}</title></track>",
+                            escape(url),
+                            escape(title)
+                        )
+                    })
+                    .collect();
+                format!(
+                    "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n\
+                     <playlist version=\"1\" xmlns=\"http://xspf.org/ns/0/\">\
+                     <title>{}</title>\
+                     <trackList>{tracks}</trackList>\
+                     </playlist>\n",
+                    escape(title)
+                )
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn urls() -> Vec<String> {
+        vec!["http://radio:3000/stream.mp3".to_owned()]
+    }
+
+    #[test]
+    fn formats_by_path() {
+        assert!(matches!(
+            Format::from_path("/listen.m3u"),
+            Some(Format::M3u)
+        ));
+        assert!(matches!(
+            Format::from_path("/listen.pls"),
+            Some(Format::Pls)
+        ));
+        assert!(matches!(
+            Format::from_path("/listen.xspf"),
+            Some(Format::Xspf)
+        ));
+        assert!(Format::from_path("/listen.mp3").is_none());
+    }
+
+    #[test]
+    fn renders_m3u() {
+        assert_eq!(
+            Format::M3u.render("Radio", &urls()),
+            "#EXTM3U\n#EXTINF:-1,Radio\nhttp://radio:3000/stream.mp3\n"
+        );
+    }
+
+    #[test]
+    fn renders_pls() {
+        assert_eq!(
+            Format::Pls.render("Radio", &urls()),
+            "[playlist]\n\
+             File1=http://radio:3000/stream.mp3\nTitle1=Radio\nLength1=-1\n\
+             NumberOfEntries=1\nVersion=2\n"
+        );
+    }
+
+    #[test]
+    fn renders_escaped_xspf() {
+        let playlist = Format::Xspf.render("Rock & Roll", &urls());
+        assert!(playlist.starts_with("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n"));
+        assert!(playlist.contains("<title>Rock &amp; Roll</title><trackList>"));
+        assert!(playlist
+            .contains("<track><location>http://radio:3000/stream.mp3</location><title>Rock &amp; Roll</title></track>"));
+    }
+}
@@ -0,0 +1,307 @@
+use std::net::SocketAddr;
+use std::path::Path;
+use std::sync::Arc;
+use std::time::Duration;
+
+use chrono::Local;
+use serde::Deserialize;
+use tokio::net::UdpSocket;
+
+use crate::streamer;
+use crate::upnp;
+
+const AV_TRANSPORT: &str = "urn:schemas-upnp-org:service:AVTransport:1";
+/// How long to collect answers when searching for a renderer.
+const SEARCH_TIME: Duration = Duration::from_secs(3);
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// A UPnP MediaRenderer, like a network speaker, to start the station on.
+pub struct Renderer {
+    /// Used in the control endpoint.
+    name: String,
+    /// Name the device advertises itself with, to find it over SSDP.
+    friendly_name: String,
+    /// Device description URL, to skip the search.
+    location: Option<reqwest::Url>,
+    cron: Option<cron::Schedule>,
+    /// Stream URL for the renderer, when it can't reach the station directly.
+    stream_url: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct RenderersFile {
+    #[serde(default, rename = "renderer")]
+    renderers: Vec<RendererEntry>,
+}
+
+#[derive(Deserialize)]
+struct RendererEntry {
+    name: String,
+    friendly_name: Option<String>,
+    location: Option<String>,
+    cron: Option<String>,
+    stream_url: Option<String>,
+}
+
+impl Renderer {
+    fn from_entry(entry: RendererEntry) -> anyhow::Result<Self> {
+        let cron = entry
+            .cron
+            .map(|cron| {
+                cron.parse()
+                    .map_err(|e| anyhow::anyhow!("Invalid cron expression {:?}: {}", cron, e))
+            })
+            .transpose()?;
+        let location = entry
+            .location
+            .map(|location| reqwest::Url::parse(&location))
+            .transpose()?;
+        Ok(Self {
+            friendly_name: entry.friendly_name.unwrap_or_else(|| entry.name.clone()),
+            name: entry.name,
+            location,
+            cron,
+            stream_url: entry.stream_url,
+        })
+    }
+}
+
+/// The configured renderers, which play the station when scheduled or
+/// asked to.
+#[derive(Clone)]
+pub struct Renderers {
+    renderers: Arc<Vec<Renderer>>,
+    /// Title of the stream on the renderers.
+    station_name: String,
+    /// HTTP port of the station.
+    port: u16,
+    client: reqwest::Client,
+}
+
+impl Renderers {
+    /// Read the renderers from a toml file.
+    pub fn load(path: &Path, station_name: String, port: u16) -> anyhow::Result<Self> {
+        let file: RenderersFile = toml::from_str(&std::fs::read_to_string(path)?)?;
+        let renderers = file
+            .renderers
+            .into_iter()
+            .map(|entry| {
+                let name = entry.name.clone();
+                Renderer::from_entry(entry)
+                    .map_err(|e| anyhow::anyhow!("Invalid renderer {:?}: {}", name, e))
+            })
+            .collect::<anyhow::Result<Vec<_>>>()?;
+        Ok(Self {
+            renderers: Arc::new(renderers),
+            station_name,
+            port,
+            client: reqwest::Client::builder()
+                .timeout(REQUEST_TIMEOUT)
+                .build()?,
+        })
+    }
+
+    pub fn contains(&self, name: &str) -> bool {
+        self.renderers.iter().any(|r| r.name == name)
+    }
+
+    /// Start the station on the renderer called `name`.
+    pub async fn play(&self, name: &str) -> anyhow::Result<()> {
+        let renderer = self
+            .renderers
+            .iter()
+            .find(|r| r.name == name)
+            .ok_or(anyhow::anyhow!("No renderer named {}", name))?;
+        let location = match &renderer.location {
+            Some(location) => location.clone(),
+            None => find(&renderer.friendly_name).await?,
+        };
+        let control_url = self.control_url(&location).await?;
+
+        let stream_url = match &renderer.stream_url {
+            Some(stream_url) => stream_url.clone(),
+            None => {
+                let host = control_url
+                    .host_str()
+                    .ok_or(anyhow::anyhow!("No host in {}", control_url))?;
+                let port = control_url.port_or_known_default().unwrap_or(80);
+                let peer = tokio::net::lookup_host((host, port))
+                    .await?
+                    .next()
+                    .ok_or(anyhow::anyhow!("Can't resolve {}", host))?;
+                let ip = upnp::local_ip_towards(peer)?;
+                format!("http://{ip}:{}{}", self.port, streamer::MOUNT)
+            }
+        };
+
+        let metadata = upnp::stream_metadata(&self.station_name, &stream_url);
+        self.call(
+            &control_url,
+            "SetAVTransportURI",
+            &format!(
+                "<InstanceID>0</InstanceID>\
+                 <CurrentURI>{}</CurrentURI>\
+                 <CurrentURIMetaData>{}</CurrentURIMetaData>",
+                upnp::escape(&stream_url),
+                upnp::escape(&metadata)
+            ),
+        )
+        .await?;
+        self.call(
+            &control_url,
+            "Play",
+            "<InstanceID>0</InstanceID><Speed>1</Speed>",
+        )
+        .await?;
+        tracing::info!("Playing {} on {}", stream_url, renderer.name);
+        Ok(())
+    }
+
+    /// The AVTransport control URL from the device description.
+    async fn control_url(&self, location: &reqwest::Url) -> anyhow::Result<reqwest::Url> {
+        let description = self
+            .client
+            .get(location.clone())
+            .send()
+            .await?
+            .error_for_status()?
+            .text()
+            .await?;
+        let control_url = description
+            .split("<service>")
+            .skip(1)
+            .find(|service| {
+                upnp::argument(service, "serviceType")
+                    .is_some_and(|kind| kind.starts_with(AV_TRANSPORT.trim_end_matches('1')))
+            })
+            .and_then(|service| upnp::argument(service, "controlURL"))
+            .ok_or(anyhow::anyhow!("{} has no AVTransport service", location))?;
+        let base = match upnp::argument(&description, "URLBase") {
+            Some(base) if !base.is_empty() => reqwest::Url::parse(&base)?,
+            _ => location.clone(),
+        };
+        Ok(base.join(&control_url)?)
+    }
+
+    async fn call(
+        &self,
+        control_url: &reqwest::Url,
+        action: &str,
+        arguments: &str,
+    ) -> anyhow::Result<()> {
+        let body = upnp::soap_envelope(&format!(
+            "<u:{action} xmlns:u=\"{AV_TRANSPORT}\">{arguments}</u:{action}>"
+        ));
+        let response = self
+            .client
+            .post(control_url.clone())
+            .header(reqwest::header::CONTENT_TYPE, "text/xml; charset=\"utf-8\"")
+            .header("SOAPACTION", format!("\"{AV_TRANSPORT}#{action}\""))
+            .body(body)
+            .send()
+            .await?;
+        if !response.status().is_success() {
+            let status = response.status();
+            let text = response.text().await.unwrap_or_default();
+            let description = upnp::argument(&text, "errorDescription").unwrap_or_default();
+            anyhow::bail!("{} failed with {}: {}", action, status, description);
+        }
+        Ok(())
+    }
+
+    /// Start each renderer at its scheduled times.
+    pub async fn run(self) {
+        loop {
+            let now = Local::now();
+            let Some(time) = self
+                .renderers
+                .iter()
+                .filter_map(|r| r.cron.as_ref()?.after(&now).next())
+                .min()
+            else {
+                tracing::info!("No more renderers scheduled");
+                return;
+            };
+            // Renderers scheduled at the same time all start together
+            let due: Vec<_> = self
+                .renderers
+                .iter()
+                .filter(|r| {
+                    r.cron
+                        .as_ref()
+                        .is_some_and(|cron| cron.after(&now).next() == Some(time))
+                })
+                .map(|r| r.name.clone())
+                .collect();
+            tracing::info!("Starting {} at {}", due.join(", "), time);
+            tokio::time::sleep((time - now).to_std().unwrap_or_default()).await;
+
+            for name in due {
+                if let Err(e) = self.play(&name).await {
+                    tracing::error!("Error starting renderer {}: {}", name, e);
+                }
+            }
+        }
+    }
+}
+
+/// Search for a renderer with the given friendly name over SSDP, returning
+/// its description URL.
+async fn find(friendly_name: &str) -> anyhow::Result<reqwest::Url> {
+    let socket = UdpSocket::bind(("0.0.0.0", 0)).await?;
+    let group = SocketAddr::from((upnp::SSDP_ADDR, upnp::SSDP_PORT));
+    let search = format!(
+        "M-SEARCH * HTTP/1.1\r\n\
+         HOST: {group}\r\n\
+         MAN: \"ssdp:discover\"\r\n\
+         MX: 2\r\n\
+         ST: {AV_TRANSPORT}\r\n\
+         USER-AGENT: {}\r\n\r\n",
+        upnp::server_header()
+    );
+    socket.send_to(search.as_bytes(), group).await?;
+
+    let client = reqwest::Client::builder()
+        .timeout(REQUEST_TIMEOUT)
+        .build()?;
+    let mut checked = Vec::new();
+    let mut buffer = [0; 2048];
+    let deadline = tokio::time::Instant::now() + SEARCH_TIME;
+    while let Ok(received) = tokio::time::timeout_at(deadline, socket.recv_from(&mut buffer)).await
+    {
+        let Ok((size, _)) = received else {
+            continue;
+        };
+        let message = String::from_utf8_lossy(&buffer[..size]);
+        let Some(location) = message.lines().find_map(|line| {
+            let (name, value) = line.split_once(':')?;
+            name.trim()
+                .eq_ignore_ascii_case("LOCATION")
+                .then(|| value.trim().to_owned())
+        }) else {
+            continue;
+        };
+        // Devices answer once for every matching service
+        if checked.contains(&location) {
+            continue;
+        }
+        checked.push(location.clone());
+
+        let Ok(location) = reqwest::Url::parse(&location) else {
+            continue;
+        };
+        let description = match client.get(location.clone()).send().await {
+            Ok(response) => response.text().await.unwrap_or_default(),
+            Err(e) => {
+                tracing::debug!("Error fetching {}: {}", location, e);
+                continue;
+            }
+        };
+        if upnp::argument(&description, "friendlyName")
+            .is_some_and(|name| name.eq_ignore_ascii_case(friendly_name))
+        {
+            return Ok(location);
+        }
+    }
+    anyhow::bail!("No renderer named {} found", friendly_name)
+}
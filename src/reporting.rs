@@ -0,0 +1,93 @@
+use std::sync::Arc;
+use std::time::Duration;
+
+use tokio::sync::broadcast::error::RecvError;
+
+use crate::jellyfin::{Audio, JellyfinClient, PlaybackReport};
+use crate::player::{PlayerController, PlayerEvent, Track};
+
+const PROGRESS_INTERVAL: Duration = Duration::from_secs(10);
+
+/// Report the songs played on the stream as a Jellyfin playback session, so
+/// the station shows up on the dashboard and play counts are updated.
+pub async fn report_playback(
+    client: Arc<JellyfinClient>,
+    user_id: String,
+    mut player_controller: PlayerController,
+) {
+    let mut events = player_controller.subscribe();
+    let mut current: Option<Audio> = None;
+    let mut paused = false;
+    let mut progress = tokio::time::interval(PROGRESS_INTERVAL);
+
+    loop {
+        tokio::select! {
+            event = events.recv() => {
+                let event = match event {
+                    Ok(event) => event,
+                    Err(RecvError::Lagged(skipped)) => {
+                        tracing::warn!("Playback reporting missed {} player events", skipped);
+                        continue;
+                    }
+                    Err(RecvError::Closed) => return,
+                };
+                match event {
+                    PlayerEvent::Started(Track::Song(audio)) => {
+                        report(&client, PlaybackReport::Started, &audio, Duration::ZERO, paused)
+                            .await;
+                        current = Some(audio);
+                    }
+                    PlayerEvent::Finished { track: Track::Song(audio), position } => {
+                        report(&client, PlaybackReport::Stopped, &audio, position, false).await;
+                        // Sessions of users update the play count themselves
+                        if !client.is_user_session() {
+                            if let Err(e) = client.mark_played(&user_id, &audio.id).await {
+                                tracing::warn!("Error marking {} as played: {}", audio.name, e);
+                            }
+                        }
+                        current = None;
+                    }
+                    PlayerEvent::Skipped { track: Track::Song(audio), position } => {
+                        report(&client, PlaybackReport::Stopped, &audio, position, false).await;
+                        current = None;
+                    }
+                    PlayerEvent::Paused { track, position } => {
+                        paused = true;
+                        if let Track::Song(audio) = &track {
+                            report(&client, PlaybackReport::Progress, audio, position, true).await;
+                        }
+                    }
+                    PlayerEvent::Resumed { track, position } => {
+                        paused = false;
+                        if let Track::Song(audio) = &track {
+                            report(&client, PlaybackReport::Progress, audio, position, false).await;
+                        }
+                    }
+                    _ => {}
+                }
+            }
+            _ = progress.tick() => {
+                if let Some(audio) = &current {
+                    // The player knows where the song is, pauses included
+                    let position = player_controller.position().await;
+                    report(&client, PlaybackReport::Progress, audio, position, paused).await;
+                }
+            }
+        }
+    }
+}
+
+async fn report(
+    client: &JellyfinClient,
+    report: PlaybackReport,
+    audio: &Audio,
+    position: Duration,
+    paused: bool,
+) {
+    if let Err(e) = client
+        .report_playback(report, &audio.id, position, paused)
+        .await
+    {
+        tracing::warn!("Error reporting playback of {}: {}", audio.name, e);
+    }
+}
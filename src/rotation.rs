@@ -0,0 +1,170 @@
+use std::collections::VecDeque;
+
+use rand::seq::IteratorRandom;
+
+use crate::jellyfin::{Audio, ItemKind, JellyfinClient};
+
+/// How many played songs are remembered to re-seed the mix and avoid repeats.
+const HISTORY_SIZE: usize = 50;
+/// Fetch a new mix once fewer songs than this are left.
+const MIX_LOW_WATERMARK: usize = 3;
+
+/// Where an instant mix starts from. Configured as `song:<name>`,
+/// `artist:<name>`, `genre:<name>` or a plain Jellyfin item id.
+#[derive(Debug, Clone)]
+pub enum Seed {
+    Item(String),
+    Named(ItemKind, String),
+}
+
+impl std::str::FromStr for Seed {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let seed = match s.split_once(':') {
+            Some(("song", name)) => Seed::Named(ItemKind::Song, name.trim().to_owned()),
+            Some(("artist", name)) => Seed::Named(ItemKind::Artist, name.trim().to_owned()),
+            Some(("genre", name)) => Seed::Named(ItemKind::Genre, name.trim().to_owned()),
+            Some((kind, _)) => anyhow::bail!("Unknown seed type {kind:?}"),
+            None => Seed::Item(s.trim().to_owned()),
+        };
+        Ok(seed)
+    }
+}
+
+enum Mode {
+    Random,
+    InstantMix {
+        seed: Option<Seed>,
+        mix_size: u32,
+        pending: VecDeque<Audio>,
+    },
+}
+
+/// Decides which song is queued next.
+pub struct Rotation {
+    user_id: String,
+    collection_id: String,
+    mode: Mode,
+    history: VecDeque<String>,
+}
+
+impl Rotation {
+    /// Pick random songs from the whole collection.
+    pub fn random(user_id: String, collection_id: String) -> Self {
+        Self {
+            user_id,
+            collection_id,
+            mode: Mode::Random,
+            history: VecDeque::new(),
+        }
+    }
+
+    /// Follow Jellyfin instant mixes, starting at `seed`. When the mix runs
+    /// low, a recently played song becomes the next seed.
+    pub fn instant_mix(user_id: String, collection_id: String, seed: Seed, mix_size: u32) -> Self {
+        Self {
+            user_id,
+            collection_id,
+            mode: Mode::InstantMix {
+                seed: Some(seed),
+                mix_size,
+                pending: VecDeque::new(),
+            },
+            history: VecDeque::new(),
+        }
+    }
+
+    pub async fn next(&mut self, client: &JellyfinClient) -> anyhow::Result<Audio> {
+        let item = match &mut self.mode {
+            Mode::Random => {
+                client
+                    .random_audio(&self.user_id, &self.collection_id)
+                    .await?
+            }
+            Mode::InstantMix {
+                seed,
+                mix_size,
+                pending,
+            } => {
+                if pending.len() < MIX_LOW_WATERMARK {
+                    // The configured seed is only used up once its mix arrived
+                    let next_seed = seed.clone().or_else(|| {
+                        self.history
+                            .iter()
+                            .choose(&mut rand::thread_rng())
+                            .map(|id| Seed::Item(id.clone()))
+                    });
+                    if let Some(next_seed) = next_seed {
+                        match Self::fetch_mix(
+                            client,
+                            &self.user_id,
+                            &self.collection_id,
+                            &next_seed,
+                            *mix_size,
+                        )
+                        .await
+                        {
+                            Ok(mix) => {
+                                tracing::info!("Fetched instant mix from {:?}", next_seed);
+                                *seed = None;
+                                let mix: Vec<Audio> = mix
+                                    .into_iter()
+                                    .filter(|a| {
+                                        !self.history.contains(&a.id)
+                                            && !pending.iter().any(|p| p.id == a.id)
+                                    })
+                                    .collect();
+                                pending.extend(mix);
+                            }
+                            Err(e) => tracing::error!("Error fetching instant mix: {}", e),
+                        }
+                    }
+                }
+
+                match pending.pop_front() {
+                    Some(item) => item,
+                    None => {
+                        tracing::warn!("Instant mix is empty, falling back to a random song");
+                        client
+                            .random_audio(&self.user_id, &self.collection_id)
+                            .await?
+                    }
+                }
+            }
+        };
+
+        self.history.push_back(item.id.clone());
+        if self.history.len() > HISTORY_SIZE {
+            self.history.pop_front();
+        }
+        Ok(item)
+    }
+
+    async fn fetch_mix(
+        client: &JellyfinClient,
+        user_id: &str,
+        collection_id: &str,
+        seed: &Seed,
+        mix_size: u32,
+    ) -> anyhow::Result<Vec<Audio>> {
+        let item_id = match seed {
+            Seed::Item(id) => id.clone(),
+            Seed::Named(kind, name) => {
+                client
+                    .find_item(user_id, collection_id, *kind, name)
+                    .await?
+                    .id
+            }
+        };
+        let mix = client.instant_mix(user_id, &item_id, mix_size).await?;
+
+        // Instant mixes draw from the whole library, keep to the collection
+        let ids: Vec<&str> = mix.iter().map(|a| a.id.as_str()).collect();
+        let in_collection = client.in_collection(user_id, collection_id, &ids).await?;
+        Ok(mix
+            .into_iter()
+            .filter(|a| in_collection.contains(&a.id))
+            .collect())
+    }
+}
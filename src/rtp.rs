@@ -0,0 +1,121 @@
+use std::net::SocketAddr;
+use std::time::Duration;
+
+use futures_util::StreamExt;
+use tokio::net::UdpSocket;
+
+use crate::streamer::{self, StreamerBackend};
+
+/// Dynamic payload type announced in the SDP as L16/48000/2.
+const PAYLOAD_TYPE: u8 = 96;
+const HEADER_SIZE: usize = 12;
+
+/// Sends the station as RTP with an uncompressed L16 payload, to a unicast
+/// or multicast address.
+pub struct Output {
+    socket: UdpSocket,
+    destination: SocketAddr,
+    ttl: u32,
+    /// Frames, samples of all channels, per packet.
+    frames_per_packet: usize,
+    ptime: Duration,
+    ssrc: u32,
+}
+
+impl Output {
+    pub async fn bind(destination: SocketAddr, ttl: u32, ptime: Duration) -> anyhow::Result<Self> {
+        let bind: SocketAddr = if destination.is_ipv4() {
+            ([0, 0, 0, 0], 0).into()
+        } else {
+            ([0u16; 8], 0).into()
+        };
+        let socket = UdpSocket::bind(bind).await?;
+        if destination.ip().is_multicast() {
+            if destination.is_ipv4() {
+                socket.set_multicast_ttl_v4(ttl)?;
+            }
+        } else {
+            socket.set_ttl(ttl)?;
+        }
+        socket.connect(destination).await?;
+
+        let frames_per_packet = (streamer::SAMPLE_RATE as f64 * ptime.as_secs_f64()) as usize;
+        if frames_per_packet == 0
+            || frames_per_packet * streamer::CHANNEL_COUNT as usize * 2 + HEADER_SIZE > 1472
+        {
+            anyhow::bail!("RTP packet time {:?} doesn't fit a packet", ptime);
+        }
+        Ok(Self {
+            socket,
+            destination,
+            ttl,
+            frames_per_packet,
+            ptime,
+            ssrc: rand::random(),
+        })
+    }
+
+    /// Session description for receivers.
+    pub fn sdp(&self, name: &str) -> anyhow::Result<String> {
+        let origin = self.socket.local_addr()?.ip();
+        let family = if origin.is_ipv4() { "IP4" } else { "IP6" };
+        let destination = self.destination.ip();
+        let connection = if destination.is_multicast() && destination.is_ipv4() {
+            format!("{destination}/{}", self.ttl)
+        } else {
+            destination.to_string()
+        };
+        Ok(format!(
+            "v=0\r\n\
+             o=- {ssrc} 0 IN {family} {origin}\r\n\
+             s={name}\r\n\
+             c=IN {family} {connection}\r\n\
+             t=0 0\r\n\
+             m=audio {port} RTP/AVP {PAYLOAD_TYPE}\r\n\
+             a=rtpmap:{PAYLOAD_TYPE} L16/{rate}/{channels}\r\n\
+             a=ptime:{ptime}\r\n\
+             a=sendonly\r\n",
+            ssrc = self.ssrc,
+            port = self.destination.port(),
+            rate = streamer::SAMPLE_RATE,
+            channels = streamer::CHANNEL_COUNT,
+            ptime = self.ptime.as_secs_f64() * 1000.0,
+        ))
+    }
+
+    pub async fn run(self, streamer: StreamerBackend) {
+        tracing::info!("Sending RTP to {}", self.destination);
+        let samples_per_packet = self.frames_per_packet * streamer::CHANNEL_COUNT as usize;
+        let mut sequence: u16 = rand::random();
+        let mut timestamp: u32 = rand::random();
+        let mut first = true;
+        let mut pending: Vec<i16> = Vec::new();
+
+        let mut pcm = streamer.subscribe();
+        while let Some(data) = pcm.next().await {
+            pending.extend_from_slice(data.as_slice());
+            let mut start = 0;
+            while pending.len() - start >= samples_per_packet {
+                let samples = &pending[start..start + samples_per_packet];
+                start += samples_per_packet;
+
+                let mut packet = Vec::with_capacity(HEADER_SIZE + samples.len() * 2);
+                packet.push(0x80); // Version 2, no padding, extension or CSRCs
+                packet.push(PAYLOAD_TYPE | if first { 0x80 } else { 0 });
+                packet.extend_from_slice(&sequence.to_be_bytes());
+                packet.extend_from_slice(&timestamp.to_be_bytes());
+                packet.extend_from_slice(&self.ssrc.to_be_bytes());
+                // L16 is big endian
+                packet.extend(samples.iter().flat_map(|s| s.to_be_bytes()));
+
+                if let Err(e) = self.socket.send(&packet).await {
+                    tracing::warn!("Error sending RTP to {}: {}", self.destination, e);
+                }
+                first = false;
+                sequence = sequence.wrapping_add(1);
+                timestamp = timestamp.wrapping_add(self.frames_per_packet as u32);
+            }
+            pending.drain(..start);
+        }
+    }
+}
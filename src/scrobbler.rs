@@ -0,0 +1,240 @@
+use std::path::PathBuf;
+use std::time::Duration;
+
+use serde::{Deserialize, Serialize};
+use tokio::sync::broadcast::{self, error::RecvError};
+
+use crate::jellyfin::Audio;
+use crate::player::{PlayerEvent, Track};
+
+/// Listens count once a track played for half its length or this long.
+const SCROBBLE_THRESHOLD: Duration = Duration::from_secs(4 * 60);
+/// Tracks shorter than this are never submitted.
+const MINIMUM_TRACK_LENGTH: Duration = Duration::from_secs(30);
+const RETRY_INTERVAL: Duration = Duration::from_secs(60);
+const MAX_LISTENS_PER_REQUEST: usize = 100;
+
+#[derive(Serialize, Deserialize, Clone)]
+struct Listen {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    listened_at: Option<i64>,
+    track_metadata: TrackMetadata,
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+struct TrackMetadata {
+    artist_name: String,
+    track_name: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    release_name: Option<String>,
+    additional_info: AdditionalInfo,
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+struct AdditionalInfo {
+    media_player: String,
+    submission_client: String,
+    submission_client_version: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    duration_ms: Option<u64>,
+}
+
+impl Listen {
+    fn new(audio: &Audio, listened_at: Option<i64>) -> Self {
+        Self {
+            listened_at,
+            track_metadata: TrackMetadata {
+                artist_name: audio.artists.join(", "),
+                track_name: audio.name.clone(),
+                release_name: audio.album.clone(),
+                additional_info: AdditionalInfo {
+                    media_player: "jellyfin-radio".to_owned(),
+                    submission_client: "jellyfin-radio".to_owned(),
+                    submission_client_version: env!("CARGO_PKG_VERSION").to_owned(),
+                    duration_ms: audio.duration().map(|d| d.as_millis() as u64),
+                },
+            },
+        }
+    }
+}
+
+/// Submits played tracks to a ListenBrainz compatible API. Listens which
+/// could not be submitted are kept in a queue, which is stored on disk if
+/// a queue file is configured.
+pub struct Scrobbler {
+    client: reqwest::Client,
+    url: String,
+    token: String,
+    queue: Vec<Listen>,
+    queue_file: Option<PathBuf>,
+}
+
+impl Scrobbler {
+    pub fn new(url: String, token: String, queue_file: Option<PathBuf>) -> Self {
+        let queue = queue_file
+            .as_ref()
+            .and_then(|path| std::fs::read(path).ok())
+            .and_then(|data| match serde_json::from_slice(&data) {
+                Ok(queue) => Some(queue),
+                Err(e) => {
+                    tracing::warn!("Ignoring unreadable scrobble queue: {}", e);
+                    None
+                }
+            })
+            .unwrap_or_default();
+        Self {
+            client: reqwest::Client::new(),
+            url: url.trim_end_matches('/').to_owned(),
+            token,
+            queue,
+            queue_file,
+        }
+    }
+
+    pub async fn run(mut self, mut events: broadcast::Receiver<PlayerEvent>) {
+        let mut started_at = None;
+        let mut retry = tokio::time::interval(RETRY_INTERVAL);
+
+        loop {
+            tokio::select! {
+                event = events.recv() => {
+                    let event = match event {
+                        Ok(event) => event,
+                        Err(RecvError::Lagged(skipped)) => {
+                            tracing::warn!("Scrobbler missed {} player events", skipped);
+                            // The start time may belong to a song whose end was missed
+                            started_at = None;
+                            continue;
+                        }
+                        Err(RecvError::Closed) => return,
+                    };
+                    match event {
+                        PlayerEvent::Started(Track::Interstitial(_)) => started_at = None,
+                        PlayerEvent::Started(Track::Song(audio)) => {
+                            started_at = Some(chrono::Utc::now().timestamp());
+                            if let Err(e) = self.submit("playing_now", &[Listen::new(&audio, None)]).await {
+                                tracing::warn!("Error submitting now playing: {}", e);
+                            }
+                        }
+                        PlayerEvent::Finished { track: Track::Song(audio), position }
+                        | PlayerEvent::Skipped { track: Track::Song(audio), position }
+                            if Self::counts_as_listen(&audio, position) =>
+                        {
+                            // ListenBrainz requires the time, so derive it if the start was missed
+                            let listened_at = started_at.take().unwrap_or_else(|| {
+                                chrono::Utc::now().timestamp() - position.as_secs() as i64
+                            });
+                            self.queue.push(Listen::new(&audio, Some(listened_at)));
+                            self.flush().await;
+                        }
+                        _ => {}
+                    }
+                }
+                _ = retry.tick() => {
+                    if !self.queue.is_empty() {
+                        self.flush().await;
+                    }
+                }
+            }
+        }
+    }
+
+    fn counts_as_listen(audio: &Audio, position: Duration) -> bool {
+        match audio.duration() {
+            Some(duration) if duration < MINIMUM_TRACK_LENGTH => false,
+            Some(duration) => position >= (duration / 2).min(SCROBBLE_THRESHOLD),
+            None => position >= SCROBBLE_THRESHOLD,
+        }
+    }
+
+    /// Submit all queued listens. Listens the server rejects are dropped,
+    /// the others are kept for later if it can't be reached.
+    async fn flush(&mut self) {
+        let mut batch_size = MAX_LISTENS_PER_REQUEST;
+        while !self.queue.is_empty() {
+            let count = self.queue.len().min(batch_size);
+            let listen_type = if count == 1 { "single" } else { "import" };
+            match self.submit(listen_type, &self.queue[..count]).await {
+                Ok(()) => {
+                    self.queue.drain(..count);
+                }
+                Err(e) if Self::rejected(&e) && count > 1 => {
+                    tracing::warn!("Listens rejected, submitting one by one: {}", e);
+                    batch_size = 1;
+                }
+                Err(e) if Self::rejected(&e) => {
+                    let listen = self.queue.remove(0);
+                    tracing::warn!(
+                        "Dropping listen of {} rejected by the server: {}",
+                        listen.track_metadata.track_name,
+                        e
+                    );
+                }
+                Err(e) => {
+                    tracing::warn!(
+                        "Error submitting {} listens, retrying later: {}",
+                        self.queue.len(),
+                        e
+                    );
+                    break;
+                }
+            }
+        }
+        self.store_queue().await;
+    }
+
+    /// Whether the server refused the listens themselves, so sending them
+    /// again can't help. A bad token or rate limiting is worth retrying.
+    fn rejected(error: &anyhow::Error) -> bool {
+        error
+            .downcast_ref::<reqwest::Error>()
+            .and_then(|e| e.status())
+            .is_some_and(|status| {
+                status.is_client_error()
+                    && ![
+                        reqwest::StatusCode::UNAUTHORIZED,
+                        reqwest::StatusCode::FORBIDDEN,
+                        reqwest::StatusCode::TOO_MANY_REQUESTS,
+                    ]
+                    .contains(&status)
+            })
+    }
+
+    async fn submit(&self, listen_type: &str, listens: &[Listen]) -> anyhow::Result<()> {
+        #[derive(Serialize)]
+        struct Submission<'a> {
+            listen_type: &'a str,
+            payload: &'a [Listen],
+        }
+
+        self.client
+            .post(format!("{}/1/submit-listens", self.url))
+            .header(
+                reqwest::header::AUTHORIZATION,
+                format!("Token {}", self.token),
+            )
+            .json(&Submission {
+                listen_type,
+                payload: listens,
+            })
+            .send()
+            .await?
+            .error_for_status()?;
+        Ok(())
+    }
+
+    async fn store_queue(&self) {
+        let Some(path) = &self.queue_file else {
+            return;
+        };
+        let result = async {
+            let data = serde_json::to_vec(&self.queue)?;
+            tokio::fs::write(path, data).await?;
+            anyhow::Ok(())
+        }
+        .await;
+        if let Err(e) = result {
+            tracing::warn!("Could not store scrobble queue at {:?}: {}", path, e);
+        }
+    }
+}
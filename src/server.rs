@@ -0,0 +1,388 @@
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::Duration;
+
+use bytes::Bytes;
+use futures_util::{Stream, StreamExt};
+use http_body_util::combinators::BoxBody;
+use http_body_util::{BodyExt, Full, StreamBody};
+use hyper::body::Frame;
+use hyper::service::Service;
+use hyper::{body, Method, Request, Response, StatusCode};
+use tokio::sync::watch;
+use tokio_stream::wrappers::IntervalStream;
+
+use crate::announce::Announcer;
+use crate::events::EventBus;
+use crate::jellyfin::JellyfinClient;
+use crate::nowplaying;
+use crate::playlist;
+use crate::renderers::Renderers;
+use crate::status::Status;
+use crate::streamer::{self, StreamerBackend};
+use crate::upnp::escape;
+use crate::upnp::MediaServer;
+
+const INDEX: &str = include_str!("web/index.html");
+/// Size cover art is scaled down to, unless asked for with `?size=`.
+const COVER_SIZE: u32 = 512;
+/// Sizes covers are scaled to, so arbitrary sizes can't flood the cache or
+/// keep Jellyfin busy scaling.
+const COVER_SIZES: [u32; 4] = [128, 256, 512, 1024];
+/// Comments sent on idle event streams, so proxies don't close them.
+const KEEP_ALIVE: Duration = Duration::from_secs(30);
+
+type Body = BoxBody<Bytes, anyhow::Error>;
+type ResponseFuture =
+    std::pin::Pin<Box<dyn std::future::Future<Output = anyhow::Result<Response<Body>>> + Send>>;
+
+/// Routes requests to the endpoints. The stream is served on its mount, and
+/// on `/` for anything but browsers. Other paths are not found.
+#[derive(Clone)]
+pub struct Server {
+    pub streamer: StreamerBackend,
+    pub announcer: Option<Announcer>,
+    /// Session description of the RTP output, if enabled.
+    pub sdp: Option<String>,
+    pub upnp: Option<MediaServer>,
+    pub renderers: Option<Renderers>,
+    /// Bearer token for the control endpoints.
+    pub control_token: Option<String>,
+    /// Title of the station in playlists and on the web page.
+    pub station_name: String,
+    pub now_playing: watch::Receiver<nowplaying::State>,
+    pub client: Arc<JellyfinClient>,
+    pub events: EventBus,
+    pub status: Status,
+    /// Address of the client, set for each connection.
+    pub peer: Option<SocketAddr>,
+}
+
+impl Service<Request<body::Incoming>> for Server {
+    type Response = Response<Body>;
+
+    type Error = anyhow::Error;
+
+    type Future = ResponseFuture;
+
+    fn call(&self, mut req: Request<body::Incoming>) -> Self::Future {
+        if let Some(peer) = self.peer {
+            req.extensions_mut().insert(peer);
+        }
+        if req.method() == Method::GET {
+            if let Some(id) = req
+                .uri()
+                .path()
+                .strip_prefix("/items/")
+                .and_then(|path| path.strip_suffix("/cover"))
+                // Item ids are hex, anything else would end up elsewhere in Jellyfin
+                .filter(|id| id.chars().all(|c| c.is_ascii_alphanumeric() || c == '-'))
+            {
+                let size = cover_size(&req);
+                return Box::pin(cover(self.client.clone(), id.to_owned(), size, true));
+            }
+            if let Some(format) = playlist::Format::from_path(req.uri().path()) {
+                let urls = [format!("{}{}", external_url(&req), streamer::MOUNT)];
+                let body = format.render(&self.station_name, &urls);
+                return Box::pin(async move {
+                    Ok(respond_with(StatusCode::OK, format.content_type(), body))
+                });
+            }
+        }
+        if req.method() == Method::POST {
+            let path = req.uri().path();
+            if let Some(name) = path
+                .strip_prefix("/renderers/")
+                .and_then(|path| path.strip_suffix("/play"))
+            {
+                // Names may contain spaces and the like, sent encoded
+                let Ok(name) = percent_encoding::percent_decode_str(name).decode_utf8() else {
+                    return Box::pin(async {
+                        Ok(respond(StatusCode::BAD_REQUEST, "Invalid renderer name"))
+                    });
+                };
+                return self.play_on_renderer(&req, name.into_owned());
+            }
+        }
+        match (req.method(), req.uri().path()) {
+            (&Method::POST, "/announce") => {
+                let Some(announcer) = self.authorized_announcer(&req) else {
+                    return Box::pin(async { Ok(unauthorized()) });
+                };
+                Box::pin(announcer.upload(req))
+            }
+            (&Method::GET, "/announce/live") => {
+                let Some(announcer) = self.authorized_announcer(&req) else {
+                    return Box::pin(async { Ok(unauthorized()) });
+                };
+                let response = announcer.live(req);
+                Box::pin(async { response })
+            }
+            // Browsers get the web player, players the stream
+            (&Method::GET, "/") if accepts_html(&req) => {
+                let page = INDEX.replace("{{station}}", &escape(&self.station_name));
+                Box::pin(async {
+                    Ok(respond_with(
+                        StatusCode::OK,
+                        "text/html; charset=utf-8",
+                        page,
+                    ))
+                })
+            }
+            (&Method::GET, "/nowplaying/cover.jpg") => {
+                let current = self.now_playing.borrow().current.clone();
+                let Some(song) = current else {
+                    return Box::pin(async { Ok(respond(StatusCode::NOT_FOUND, "Not found")) });
+                };
+                let size = cover_size(&req);
+                Box::pin(cover(self.client.clone(), song.image_id, size, false))
+            }
+            (&Method::GET, "/nowplaying/events") => {
+                let response = nowplaying::events(self.now_playing.clone());
+                Box::pin(async { Ok(response) })
+            }
+            (&Method::GET, "/events") => {
+                let response = crate::events::feed(self.events.clone(), req);
+                Box::pin(async { response })
+            }
+            (&Method::GET, "/status-json.xsl") => {
+                let json = self.status.render(&req, &self.streamer, &self.now_playing);
+                let mut response = respond_with(StatusCode::OK, "application/json", json);
+                // Like Icecast, for dashboards on other origins
+                response.headers_mut().insert(
+                    hyper::header::ACCESS_CONTROL_ALLOW_ORIGIN,
+                    hyper::header::HeaderValue::from_static("*"),
+                );
+                Box::pin(async { Ok(response) })
+            }
+            (&Method::GET, "/stream.sdp") if self.sdp.is_some() => {
+                let sdp = self.sdp.clone().unwrap_or_default();
+                Box::pin(async { Ok(respond_with(StatusCode::OK, "application/sdp", sdp)) })
+            }
+            (_, path) if path.starts_with("/upnp/") && self.upnp.is_some() => {
+                let upnp = self.upnp.clone().unwrap();
+                Box::pin(upnp.handle(req))
+            }
+            (&Method::GET, streamer::MOUNT | "/") => self.streamer.call(req),
+            // Players probing the stream, without counting as listeners
+            (&Method::HEAD, streamer::MOUNT | "/") => {
+                Box::pin(async { Ok(streamer::response(BoxBody::default())) })
+            }
+            _ => Box::pin(async { Ok(respond(StatusCode::NOT_FOUND, "Not found")) }),
+        }
+    }
+}
+
+impl Server {
+    fn authorized_announcer<B>(&self, req: &Request<B>) -> Option<Announcer> {
+        self.announcer
+            .as_ref()
+            .filter(|announcer| announcer.authorized(req))
+            .cloned()
+    }
+
+    fn play_on_renderer<B>(&self, req: &Request<B>, name: String) -> ResponseFuture {
+        let Some(renderers) = self.renderers.clone() else {
+            return Box::pin(async { Ok(respond(StatusCode::NOT_FOUND, "Not found")) });
+        };
+        if !self
+            .control_token
+            .as_ref()
+            .is_some_and(|token| authorized(req, token))
+        {
+            return Box::pin(async { Ok(unauthorized()) });
+        }
+        if !renderers.contains(&name) {
+            return Box::pin(async { Ok(respond(StatusCode::NOT_FOUND, "Unknown renderer")) });
+        }
+        Box::pin(async move {
+            match renderers.play(&name).await {
+                Ok(()) => Ok(respond(StatusCode::OK, format!("Playing on {name}"))),
+                Err(e) => {
+                    tracing::error!("Error starting renderer {}: {}", name, e);
+                    Ok(respond(StatusCode::BAD_GATEWAY, e.to_string()))
+                }
+            }
+        })
+    }
+}
+
+fn accepts_html<B>(req: &Request<B>) -> bool {
+    req.headers()
+        .get(hyper::header::ACCEPT)
+        .and_then(|v| v.to_str().ok())
+        .is_some_and(|v| v.contains("text/html"))
+}
+
+/// Server-Sent Events, one for each item of `events`.
+pub fn event_stream(events: impl Stream<Item = String> + Send + Sync + 'static) -> Response<Body> {
+    let keep_alive =
+        IntervalStream::new(tokio::time::interval(KEEP_ALIVE)).map(|_| ":\n\n".to_owned());
+    let stream = futures_util::stream::select(events, keep_alive)
+        .map(|event| anyhow::Ok(Frame::data(Bytes::from(event))));
+
+    Response::builder()
+        .status(StatusCode::OK)
+        .header(hyper::header::CONTENT_TYPE, "text/event-stream")
+        .header(hyper::header::CACHE_CONTROL, "no-cache")
+        .body(BoxBody::new(StreamBody::new(stream)))
+        .unwrap()
+}
+
+/// The `size` query parameter of a cover request, rounded up to the next
+/// of `COVER_SIZES`.
+fn cover_size<B>(req: &Request<B>) -> u32 {
+    let size = req
+        .uri()
+        .query()
+        .unwrap_or_default()
+        .split('&')
+        .find_map(|pair| pair.strip_prefix("size="))
+        .and_then(|size| size.parse().ok())
+        .unwrap_or(COVER_SIZE);
+    COVER_SIZES
+        .into_iter()
+        .find(|&bucket| bucket >= size)
+        .unwrap_or(COVER_SIZES[COVER_SIZES.len() - 1])
+}
+
+/// Cover art from Jellyfin, so clients need no access to it. Covers of an
+/// item never change, while the now playing one does with every song.
+async fn cover(
+    client: Arc<JellyfinClient>,
+    id: String,
+    size: u32,
+    immutable: bool,
+) -> anyhow::Result<Response<Body>> {
+    match client.image(&id, size).await {
+        Ok(image) => {
+            let mut response = respond_with(StatusCode::OK, "image/jpeg", image);
+            let cache_control = if immutable {
+                "max-age=86400"
+            } else {
+                "no-cache"
+            };
+            response.headers_mut().insert(
+                hyper::header::CACHE_CONTROL,
+                hyper::header::HeaderValue::from_static(cache_control),
+            );
+            Ok(response)
+        }
+        Err(e) => {
+            tracing::debug!("No cover for {}: {}", id, e);
+            Ok(respond(StatusCode::NOT_FOUND, "Not found"))
+        }
+    }
+}
+
+/// The URL clients reach the server at, honouring the `X-Forwarded-*`
+/// headers of a reverse proxy. Has no trailing slash.
+pub fn external_url<B>(req: &Request<B>) -> String {
+    let forwarded = |name: &str| {
+        req.headers()
+            .get(name)
+            .and_then(|v| v.to_str().ok())
+            // Proxies in a chain append, the first value is the client's
+            .and_then(|v| v.split(',').next())
+            .map(|v| v.trim().to_owned())
+            .filter(|v| !v.is_empty())
+    };
+    let proto = forwarded("X-Forwarded-Proto").unwrap_or_else(|| "http".to_owned());
+    let mut host = forwarded("X-Forwarded-Host")
+        .or_else(|| forwarded(hyper::header::HOST.as_str()))
+        .unwrap_or_else(|| "localhost".to_owned());
+    if let Some(port) = forwarded("X-Forwarded-Port") {
+        let default_port = (proto == "http" && port == "80") || (proto == "https" && port == "443");
+        // Skip if the host has a port already, minding IPv6 literals
+        let has_port = host.rsplit_once(':').is_some_and(|(_, p)| !p.contains(']'));
+        if !default_port && !has_port {
+            host = format!("{host}:{port}");
+        }
+    }
+    let prefix = forwarded("X-Forwarded-Prefix").unwrap_or_default();
+    format!("{proto}://{host}{}", prefix.trim_end_matches('/'))
+}
+
+/// Whether the request carries `token` as its bearer token.
+pub fn authorized<B>(req: &Request<B>, token: &str) -> bool {
+    req.headers()
+        .get(hyper::header::AUTHORIZATION)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.strip_prefix("Bearer "))
+        .is_some_and(|t| constant_time_eq(t.as_bytes(), token.as_bytes()))
+}
+
+/// Compare secrets in a time which doesn't depend on where they differ.
+pub fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |diff, (a, b)| diff | (a ^ b)) == 0
+}
+
+fn unauthorized() -> Response<Body> {
+    let mut response = respond(StatusCode::UNAUTHORIZED, "Unauthorized");
+    response.headers_mut().insert(
+        hyper::header::WWW_AUTHENTICATE,
+        hyper::header::HeaderValue::from_static("Bearer"),
+    );
+    response
+}
+
+/// A plain text response.
+pub fn respond(status: StatusCode, text: impl Into<String>) -> Response<Body> {
+    respond_with(status, "text/plain; charset=utf-8", text.into())
+}
+
+pub fn respond_with(
+    status: StatusCode,
+    content_type: &str,
+    body: impl Into<Bytes>,
+) -> Response<Body> {
+    Response::builder()
+        .status(status)
+        .header(hyper::header::CONTENT_TYPE, content_type)
+        .body(
+            Full::new(body.into())
+                .map_err(|never| match never {})
+                .boxed(),
+        )
+        .unwrap()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn size(query: &str) -> u32 {
+        cover_size(
+            &Request::get(format!("/nowplaying/cover.jpg{query}"))
+                .body(())
+                .unwrap(),
+        )
+    }
+
+    fn with_authorization(value: &str) -> Request<()> {
+        Request::post("/announce")
+            .header(hyper::header::AUTHORIZATION, value)
+            .body(())
+            .unwrap()
+    }
+
+    #[test]
+    fn checks_bearer_token() {
+        assert!(authorized(&with_authorization("Bearer secret"), "secret"));
+        assert!(!authorized(&with_authorization("Bearer secreT"), "secret"));
+        assert!(!authorized(&with_authorization("Bearer secret2"), "secret"));
+        assert!(!authorized(&with_authorization("Basic secret"), "secret"));
+        assert!(!authorized(&Request::get("/").body(()).unwrap(), "secret"));
+    }
+
+    #[test]
+    fn cover_sizes_are_bucketed() {
+        assert_eq!(size(""), COVER_SIZE);
+        assert_eq!(size("?size=1"), 128);
+        assert_eq!(size("?size=128"), 128);
+        assert_eq!(size("?size=129"), 256);
+        assert_eq!(size("?v=2&size=600"), 1024);
+        assert_eq!(size("?size=99999"), 1024);
+        assert_eq!(size("?size=large"), COVER_SIZE);
+    }
+}
@@ -0,0 +1,300 @@
+use std::collections::VecDeque;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use chrono::Local;
+use rand::seq::SliceRandom;
+use serde::Deserialize;
+
+use crate::interstitial;
+use crate::jellyfin::{Audio, ItemKind, JellyfinClient};
+use crate::player::{PlayerController, Track};
+
+/// A program which takes over from the rotation at a scheduled time.
+pub struct Show {
+    name: String,
+    cron: cron::Schedule,
+    source: Source,
+    /// Without a duration the show ends when all its songs have played.
+    duration: Option<Duration>,
+    intro: Option<PathBuf>,
+    shuffle: bool,
+}
+
+enum Source {
+    Playlist(String),
+    Album(String),
+}
+
+#[derive(Deserialize)]
+struct ShowsFile {
+    #[serde(default, rename = "show")]
+    shows: Vec<ShowEntry>,
+}
+
+#[derive(Deserialize)]
+struct ShowEntry {
+    name: String,
+    cron: String,
+    playlist: Option<String>,
+    album: Option<String>,
+    /// Minutes.
+    duration: Option<u64>,
+    intro: Option<PathBuf>,
+    #[serde(default)]
+    shuffle: bool,
+}
+
+impl Show {
+    fn from_entry(folder: &Path, entry: ShowEntry) -> anyhow::Result<Self> {
+        let cron = entry
+            .cron
+            .parse()
+            .map_err(|e| anyhow::anyhow!("Invalid cron expression {:?}: {}", entry.cron, e))?;
+        let source = match (entry.playlist, entry.album) {
+            (Some(playlist), None) => Source::Playlist(playlist),
+            (None, Some(album)) => Source::Album(album),
+            _ => anyhow::bail!("Set either playlist or album"),
+        };
+        Ok(Self {
+            name: entry.name,
+            cron,
+            source,
+            duration: entry
+                .duration
+                .map(|minutes| Duration::from_secs(minutes * 60)),
+            intro: entry.intro.map(|intro| folder.join(intro)),
+            shuffle: entry.shuffle,
+        })
+    }
+
+    async fn songs(
+        &self,
+        client: &JellyfinClient,
+        user_id: &str,
+        collection_id: &str,
+    ) -> anyhow::Result<Vec<Audio>> {
+        let mut songs = match &self.source {
+            Source::Playlist(name) => {
+                let playlist = client
+                    .find_item(user_id, collection_id, ItemKind::Playlist, name)
+                    .await?;
+                client.playlist_audio(user_id, &playlist.id).await?
+            }
+            Source::Album(name) => {
+                let album = client
+                    .find_item(user_id, collection_id, ItemKind::Album, name)
+                    .await?;
+                client.album_audio(user_id, &album.id).await?
+            }
+        };
+        if self.shuffle {
+            songs.shuffle(&mut rand::thread_rng());
+        }
+        Ok(songs)
+    }
+}
+
+/// Read the shows from a toml file. Intros are relative to its folder.
+pub fn load(path: &Path) -> anyhow::Result<Vec<Show>> {
+    let folder = path.parent().unwrap_or(Path::new("."));
+    let file: ShowsFile = toml::from_str(&std::fs::read_to_string(path)?)?;
+    file.shows
+        .into_iter()
+        .map(|entry| {
+            let name = entry.name.clone();
+            Show::from_entry(folder, entry)
+                .map_err(|e| anyhow::anyhow!("Invalid show {:?}: {}", name, e))
+        })
+        .collect()
+}
+
+struct Program {
+    name: String,
+    songs: VecDeque<Audio>,
+    duration: Option<Duration>,
+    /// Set once the first song is handed out, when the show takes over.
+    until: Option<tokio::time::Instant>,
+}
+
+#[derive(Default)]
+struct Slot {
+    program: Option<Program>,
+    /// Counts the shows started, to notice a start during a fetch.
+    generation: u64,
+}
+
+/// The show currently on air, if any. Its songs are played before the
+/// rotation is asked for any.
+#[derive(Clone, Default)]
+pub struct OnAir(Arc<Mutex<Slot>>);
+
+impl OnAir {
+    /// The next song of the show on air, or `None` to fall back to the
+    /// rotation.
+    pub fn next_song(&self) -> Option<Audio> {
+        let mut slot = self.0.lock().unwrap();
+        let current = slot.program.as_mut()?;
+        let now = tokio::time::Instant::now();
+        let over = current.until.is_some_and(|until| now >= until);
+        match current.songs.pop_front() {
+            Some(song) if !over => {
+                if current.until.is_none() {
+                    current.until = current.duration.map(|duration| now + duration);
+                }
+                Some(song)
+            }
+            _ => {
+                tracing::info!("Show {} ended, back to the rotation", current.name);
+                slot.program = None;
+                None
+            }
+        }
+    }
+
+    pub fn generation(&self) -> u64 {
+        self.0.lock().unwrap().generation
+    }
+
+    /// Call `queue` unless a show started after `generation` was taken, so a
+    /// song fetched for the rotation meanwhile doesn't play before the show.
+    pub fn queue_unless_started(&self, generation: u64, queue: impl FnOnce()) -> bool {
+        let slot = self.0.lock().unwrap();
+        if slot.generation != generation {
+            return false;
+        }
+        queue();
+        true
+    }
+
+    fn start(&self, program: Program) {
+        let mut slot = self.0.lock().unwrap();
+        slot.program = Some(program);
+        slot.generation += 1;
+    }
+}
+
+/// Put each show on air at its scheduled time.
+pub async fn run(
+    shows: Vec<Show>,
+    client: Arc<JellyfinClient>,
+    user_id: String,
+    collection_id: String,
+    on_air: OnAir,
+    mut player_controller: PlayerController,
+) {
+    loop {
+        let now = Local::now();
+        let next = shows
+            .iter()
+            .filter_map(|show| Some((show.cron.after(&now).next()?, show)))
+            .min_by_key(|(time, _)| *time);
+        let Some((time, show)) = next else {
+            tracing::info!("No more shows scheduled");
+            return;
+        };
+        tracing::info!("Next show {} at {}", show.name, time);
+        tokio::time::sleep((time - now).to_std().unwrap_or_default()).await;
+
+        let songs = match show.songs(&client, &user_id, &collection_id).await {
+            Ok(songs) if !songs.is_empty() => songs,
+            Ok(_) => {
+                tracing::error!("Show {} has no songs, skipping", show.name);
+                continue;
+            }
+            Err(e) => {
+                tracing::error!("Error fetching songs of show {}: {}", show.name, e);
+                continue;
+            }
+        };
+        tracing::info!("Starting show {} with {} songs", show.name, songs.len());
+
+        on_air.start(Program {
+            name: show.name.clone(),
+            songs: songs.into(),
+            duration: show.duration,
+            until: None,
+        });
+        // The current song plays to its end, then the show takes over
+        player_controller.clear_upcoming_songs();
+        if let Some(intro) = &show.intro {
+            match interstitial::load_sound(intro) {
+                Ok(sound) => player_controller
+                    .insert_next(sound, Track::Interstitial(intro.display().to_string())),
+                Err(e) => tracing::error!("Error loading intro {:?}: {}", intro, e),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn song(id: &str) -> Audio {
+        serde_json::from_value(serde_json::json!({
+            "Id": id,
+            "Name": id,
+            "Artists": [],
+        }))
+        .unwrap()
+    }
+
+    fn program(duration: Option<Duration>) -> Program {
+        Program {
+            name: "Show".to_owned(),
+            songs: vec![song("a"), song("b")].into(),
+            duration,
+            until: None,
+        }
+    }
+
+    #[test]
+    fn clock_starts_with_first_song() {
+        let on_air = OnAir::default();
+        on_air.start(program(Some(Duration::from_secs(3600))));
+        assert!(on_air
+            .0
+            .lock()
+            .unwrap()
+            .program
+            .as_ref()
+            .unwrap()
+            .until
+            .is_none());
+        assert_eq!(on_air.next_song().unwrap().id, "a");
+        assert!(on_air
+            .0
+            .lock()
+            .unwrap()
+            .program
+            .as_ref()
+            .unwrap()
+            .until
+            .is_some());
+        assert_eq!(on_air.next_song().unwrap().id, "b");
+        assert!(on_air.next_song().is_none());
+    }
+
+    #[test]
+    fn ends_when_over() {
+        let on_air = OnAir::default();
+        on_air.start(program(Some(Duration::ZERO)));
+        assert_eq!(on_air.next_song().unwrap().id, "a");
+        assert!(on_air.next_song().is_none());
+        assert!(on_air.0.lock().unwrap().program.is_none());
+    }
+
+    #[test]
+    fn drops_songs_fetched_before_a_show() {
+        let on_air = OnAir::default();
+        let generation = on_air.generation();
+        on_air.start(program(None));
+        let mut queued = false;
+        assert!(!on_air.queue_unless_started(generation, || queued = true));
+        assert!(!queued);
+        assert!(on_air.queue_unless_started(on_air.generation(), || queued = true));
+        assert!(queued);
+    }
+}
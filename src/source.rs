@@ -0,0 +1,264 @@
+use std::io::{BufRead, BufReader, Write};
+use std::net::{SocketAddr, TcpStream};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+use awedio::sounds::wrappers::Controller;
+use awedio::sounds::SoundMixer;
+use awedio::{NextSample, Sound};
+use base64::Engine;
+use tokio::net::TcpListener;
+
+use crate::ducking::{self, DuckerController};
+use crate::events::EventBus;
+use crate::live::LiveInput;
+use crate::player::PlayerController;
+use crate::server;
+
+/// Audio collected before a live show goes on air, to ride out jitter.
+const PREBUFFER: Duration = Duration::from_secs(1);
+/// Samples decoded before they are handed to the mixer.
+const CHUNK_SIZE: usize = 4800;
+/// Time allowed for the source to send its request, and the longest it may
+/// stall afterwards before it is considered gone.
+const READ_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Fades the automation out completely while a live show is on air.
+const TAKEOVER_PROFILE: ducking::Profile = ducking::Profile {
+    attack: 3.0,
+    release: 3.0,
+    depth: 0.0,
+    curve: ducking::Curve::Smooth,
+    sidechain: false,
+};
+
+/// Credentials a source client has to send, like Icecast's source password.
+#[derive(Clone)]
+pub struct Credentials {
+    pub user: String,
+    pub password: String,
+}
+
+/// Accept Icecast style source connections (`SOURCE` or `PUT`) from tools
+/// like butt, Mixxx or ffmpeg. While one is connected it replaces the
+/// automation, which is paused and faded back in once the source leaves.
+/// `on_air` is set meanwhile, so scheduled interstitials hold off.
+pub async fn run(
+    addr: SocketAddr,
+    credentials: Credentials,
+    mixer_controller: Controller<SoundMixer>,
+    ducker_controller: DuckerController,
+    player_controller: PlayerController,
+    events: EventBus,
+    on_air: Arc<AtomicBool>,
+) -> anyhow::Result<()> {
+    let listener = TcpListener::bind(addr).await?;
+    tracing::info!("Accepting live sources on {}", addr);
+
+    loop {
+        let (tcp, peer) = listener.accept().await?;
+        let tcp = tcp.into_std()?;
+        tcp.set_nonblocking(false)?;
+
+        let credentials = credentials.clone();
+        let on_air = on_air.clone();
+        let mut mixer_controller = mixer_controller.clone();
+        let mut ducker_controller = ducker_controller.clone();
+        let mut player_controller = player_controller.clone();
+        let events = events.clone();
+        tokio::task::spawn(async move {
+            let accepted = {
+                let on_air = on_air.clone();
+                tokio::task::spawn_blocking(move || accept(tcp, &credentials, &on_air)).await
+            };
+            let decoder = match accepted {
+                Ok(Ok(decoder)) => decoder,
+                Ok(Err(e)) => {
+                    tracing::warn!("Rejected source from {}: {}", peer, e);
+                    events.source_error("live", format!("Rejected source from {peer}: {e}"));
+                    return;
+                }
+                Err(e) => {
+                    tracing::error!("Error accepting source from {}: {}", peer, e);
+                    events.source_error("live", format!("Error accepting source from {peer}: {e}"));
+                    return;
+                }
+            };
+
+            tracing::info!("Live source from {} connected, taking over", peer);
+            let live = decode(decoder, events);
+            let (live, completion_notifier) = live.with_async_completion_notifier();
+            let hold = ducker_controller.duck(TAKEOVER_PROFILE, None).await;
+            player_controller.set_paused(true);
+            mixer_controller.add(Box::new(live));
+            let _ = completion_notifier.await;
+
+            tracing::info!("Live source from {} disconnected, back to automation", peer);
+            player_controller.set_paused(false);
+            ducker_controller.release(hold);
+            on_air.store(false, Ordering::SeqCst);
+        });
+    }
+}
+
+/// Read the request of a source client and answer it. Returns the decoder
+/// for the stream which follows.
+fn accept(
+    tcp: TcpStream,
+    credentials: &Credentials,
+    on_air: &AtomicBool,
+) -> anyhow::Result<Box<dyn Sound>> {
+    tcp.set_read_timeout(Some(READ_TIMEOUT))?;
+    let mut writer = tcp.try_clone()?;
+    let mut reader = BufReader::new(tcp);
+
+    let mut request_line = String::new();
+    reader.read_line(&mut request_line)?;
+    let mut parts = request_line.split_whitespace();
+    let method = parts.next().unwrap_or_default().to_owned();
+    let mount = parts.next().unwrap_or_default().to_owned();
+
+    let mut authorization = None;
+    let mut content_type = None;
+    let mut expect_continue = false;
+    loop {
+        let mut line = String::new();
+        if reader.read_line(&mut line)? == 0 {
+            anyhow::bail!("Connection closed during handshake");
+        }
+        let line = line.trim_end();
+        if line.is_empty() {
+            break;
+        }
+        let Some((name, value)) = line.split_once(':') else {
+            continue;
+        };
+        let value = value.trim().to_owned();
+        match name.trim().to_ascii_lowercase().as_str() {
+            "authorization" => authorization = Some(value),
+            "content-type" => content_type = Some(value),
+            "expect" => expect_continue = value.eq_ignore_ascii_case("100-continue"),
+            _ => {}
+        }
+    }
+
+    if method != "SOURCE" && method != "PUT" {
+        writer.write_all(b"HTTP/1.0 405 Method Not Allowed\r\n\r\n")?;
+        anyhow::bail!("Unexpected method {:?}", method);
+    }
+    if !authorization.is_some_and(|a| authorized(&a, credentials)) {
+        writer.write_all(
+            b"HTTP/1.0 401 Unauthorized\r\nWWW-Authenticate: Basic realm=\"Icecast2 Server\"\r\n\r\n",
+        )?;
+        anyhow::bail!("Wrong credentials");
+    }
+    if on_air.swap(true, Ordering::SeqCst) {
+        writer.write_all(b"HTTP/1.0 403 Mountpoint in use\r\n\r\n")?;
+        anyhow::bail!("Another source is on air");
+    }
+
+    let result = (|| {
+        if expect_continue {
+            writer.write_all(b"HTTP/1.1 100 Continue\r\n\r\n")?;
+        } else {
+            writer.write_all(b"HTTP/1.0 200 OK\r\n\r\n")?;
+        }
+        tracing::info!("Source on {} sends {:?}", mount, content_type);
+
+        let extension = match content_type.as_deref() {
+            Some("audio/mpeg") => Some("mp3"),
+            Some("audio/ogg" | "application/ogg") => Some("ogg"),
+            Some("audio/aac" | "audio/aacp") => Some("aac"),
+            Some("audio/flac") => Some("flac"),
+            _ => None,
+        };
+        let decoder = awedio::sounds::decoders::SymphoniaDecoder::new(
+            Box::new(symphonia::core::io::ReadOnlySource::new(reader)),
+            extension,
+        )?;
+        anyhow::Ok(Box::new(decoder) as Box<dyn Sound>)
+    })();
+    if result.is_err() {
+        on_air.store(false, Ordering::SeqCst);
+    }
+    result
+}
+
+fn authorized(authorization: &str, credentials: &Credentials) -> bool {
+    let Some(encoded) = authorization.strip_prefix("Basic ") else {
+        return false;
+    };
+    let Ok(decoded) = base64::engine::general_purpose::STANDARD.decode(encoded.trim()) else {
+        return false;
+    };
+    let expected = format!("{}:{}", credentials.user, credentials.password);
+    server::constant_time_eq(&decoded, expected.as_bytes())
+}
+
+/// Decode the stream on its own thread, so a stalling source never blocks
+/// the mixer.
+fn decode(mut decoder: Box<dyn Sound>, events: EventBus) -> LiveInput {
+    let (live, sender) = LiveInput::new(decoder.sample_rate(), decoder.channel_count(), PREBUFFER);
+    std::thread::spawn(move || {
+        let mut chunk = Vec::with_capacity(CHUNK_SIZE);
+        loop {
+            match decoder.next_sample() {
+                Ok(NextSample::Sample(s)) => {
+                    chunk.push(s);
+                    if chunk.len() >= CHUNK_SIZE {
+                        let full = std::mem::replace(&mut chunk, Vec::with_capacity(CHUNK_SIZE));
+                        if sender.send(full).is_err() {
+                            return;
+                        }
+                    }
+                }
+                Ok(NextSample::MetadataChanged | NextSample::Paused) => {}
+                Ok(NextSample::Finished) => break,
+                Err(e) => {
+                    tracing::warn!("Error decoding live source: {:?}", e);
+                    events.source_error("live", format!("Error decoding live source: {e:?}"));
+                    break;
+                }
+            }
+        }
+        let _ = sender.send(chunk);
+    });
+    live
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn credentials() -> Credentials {
+        Credentials {
+            user: "source".to_owned(),
+            password: "hackme".to_owned(),
+        }
+    }
+
+    fn basic(user_password: &str) -> String {
+        format!(
+            "Basic {}",
+            base64::engine::general_purpose::STANDARD.encode(user_password)
+        )
+    }
+
+    #[test]
+    fn accepts_matching_credentials() {
+        assert!(authorized(&basic("source:hackme"), &credentials()));
+        assert!(authorized(
+            &format!("{} ", basic("source:hackme")),
+            &credentials()
+        ));
+    }
+
+    #[test]
+    fn rejects_other_credentials() {
+        assert!(!authorized(&basic("source:hackm"), &credentials()));
+        assert!(!authorized(&basic("admin:hackme"), &credentials()));
+        assert!(!authorized("Basic not base64!", &credentials()));
+        assert!(!authorized("Bearer hackme", &credentials()));
+    }
+}
@@ -0,0 +1,142 @@
+use chrono::{DateTime, Local};
+use hyper::header::HOST;
+use hyper::Request;
+use serde_json::json;
+use tokio::sync::watch;
+
+use crate::nowplaying;
+use crate::server::external_url;
+use crate::streamer::{self, StreamerBackend};
+
+const RFC_822: &str = "%a, %d %b %Y %H:%M:%S %z";
+const ISO_8601: &str = "%Y-%m-%dT%H:%M:%S%z";
+
+/// Describes the station in the shape of Icecast's `/status-json.xsl`, so
+/// tools monitoring Icecast servers work unchanged.
+#[derive(Clone)]
+pub struct Status {
+    pub name: String,
+    pub description: Option<String>,
+    pub genre: Option<String>,
+    pub started: DateTime<Local>,
+}
+
+impl Status {
+    pub fn render<B>(
+        &self,
+        req: &Request<B>,
+        streamer: &StreamerBackend,
+        now_playing: &watch::Receiver<nowplaying::State>,
+    ) -> String {
+        let base_url = external_url(req);
+        let host = req
+            .headers()
+            .get(HOST)
+            .and_then(|v| v.to_str().ok())
+            // Without the port, minding IPv6 literals
+            .map(|host| match host.rsplit_once(':') {
+                Some((host, port)) if !port.contains(']') => host,
+                _ => host,
+            })
+            .unwrap_or("localhost");
+        let title = now_playing
+            .borrow()
+            .current
+            .as_ref()
+            .map(|song| format!("{} - {}", song.artists.join(", "), song.name));
+        self.icestats(
+            host,
+            &base_url,
+            (streamer.listeners(), streamer.listener_peak()),
+            title,
+        )
+        .to_string()
+    }
+
+    /// `listeners` are the current and the peak count.
+    fn icestats(
+        &self,
+        host: &str,
+        base_url: &str,
+        listeners: (usize, usize),
+        title: Option<String>,
+    ) -> serde_json::Value {
+        // With a single mount Icecast has an object here instead of a list
+        let source = json!({
+            "audio_info": format!(
+                "channels={};samplerate={};bitrate={}",
+                streamer::CHANNEL_COUNT,
+                streamer::SAMPLE_RATE,
+                streamer::BITRATE
+            ),
+            "bitrate": streamer::BITRATE,
+            "channels": streamer::CHANNEL_COUNT,
+            "samplerate": streamer::SAMPLE_RATE,
+            "genre": self.genre.as_deref().unwrap_or("various"),
+            "listener_peak": listeners.1,
+            "listeners": listeners.0,
+            "listenurl": format!("{base_url}{}", streamer::MOUNT),
+            "server_description": self.description.as_deref().unwrap_or("Unspecified description"),
+            "server_name": self.name,
+            "server_type": "audio/mpeg",
+            "server_url": base_url,
+            "stream_start": self.started.format(RFC_822).to_string(),
+            "stream_start_iso8601": self.started.format(ISO_8601).to_string(),
+            "title": title,
+            "dummy": null,
+        });
+        json!({
+            "icestats": {
+                "admin": "icemaster@localhost",
+                "host": host,
+                "location": "Earth",
+                "server_id": format!("jellyfin-radio {}", env!("CARGO_PKG_VERSION")),
+                "server_start": self.started.format(RFC_822).to_string(),
+                "server_start_iso8601": self.started.format(ISO_8601).to_string(),
+                "source": source,
+            }
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::TimeZone;
+
+    use super::*;
+
+    #[test]
+    fn has_icecast_shape() {
+        let status = Status {
+            name: "Radio".to_owned(),
+            description: None,
+            genre: Some("Jazz".to_owned()),
+            started: Local.with_ymd_and_hms(2025, 3, 1, 12, 0, 0).unwrap(),
+        };
+        let stats = status.icestats(
+            "radio",
+            "http://radio:3000",
+            (2, 5),
+            Some("Artist - Song".to_owned()),
+        );
+        let icestats = &stats["icestats"];
+        assert_eq!(icestats["host"], "radio");
+        assert!(icestats["server_start_iso8601"]
+            .as_str()
+            .unwrap()
+            .starts_with("2025-03-01T12:00:00"));
+
+        // A single source is an object, not a list
+        let source = &icestats["source"];
+        assert!(source.is_object());
+        assert_eq!(source["listeners"], 2);
+        assert_eq!(source["listener_peak"], 5);
+        assert_eq!(source["listenurl"], "http://radio:3000/stream.mp3");
+        assert_eq!(source["server_name"], "Radio");
+        assert_eq!(source["server_description"], "Unspecified description");
+        assert_eq!(source["genre"], "Jazz");
+        assert_eq!(source["title"], "Artist - Song");
+        assert_eq!(source["bitrate"], streamer::BITRATE);
+        assert!(source["dummy"].is_null());
+    }
+}
@@ -3,6 +3,7 @@ use awedio::{manager::Manager, Sound};
 use async_broadcast::Receiver;
 use bytes::Bytes;
 use core::time::Duration;
+use futures_util::Stream;
 use futures_util::StreamExt;
 use futures_util::TryStreamExt;
 use http_body_util::{combinators::BoxBody, StreamBody};
@@ -10,20 +11,107 @@ use hyper::body::Frame;
 use hyper::service::Service;
 use hyper::{body, Request};
 use hyper::{Response, StatusCode};
+use std::net::SocketAddr;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::task::{Context, Poll};
+use std::time::Instant;
 
-const SAMPLE_RATE: u64 = 48000;
-const CHANNEL_COUNT: u64 = 2;
+use crate::events::{Event, EventBus};
+
+pub const SAMPLE_RATE: u64 = 48000;
+pub const CHANNEL_COUNT: u64 = 2;
+/// Bitrate of the MP3 stream in kbit/s.
+pub const BITRATE: u32 = 320;
+/// Path the stream is served at. Players which only know the address of the
+/// station get it on `/` as well.
+pub const MOUNT: &str = "/stream.mp3";
 
 const BUFFER_SIZE: usize = (SAMPLE_RATE / CHANNEL_COUNT / 10) as usize; // Should be an integer result of 48000 / 2 / x
 
-type Chunk = [i16; BUFFER_SIZE];
+pub type Chunk = [i16; BUFFER_SIZE];
 
 pub struct StreamerBackend {
     stream_receiver: Receiver<Box<Chunk>>,
+    events: EventBus,
+    next_listener_id: Arc<AtomicU64>,
+    listeners: Arc<Listeners>,
+}
+
+#[derive(Default)]
+struct Listeners {
+    current: AtomicUsize,
+    peak: AtomicUsize,
+}
+
+/// A client of the stream, which publishes its disconnect once the response
+/// is dropped.
+struct Listener {
+    id: u64,
+    address: Option<String>,
+    since: Instant,
+    events: EventBus,
+    listeners: Arc<Listeners>,
+}
+
+impl Listener {
+    fn connect(
+        id: u64,
+        address: Option<String>,
+        events: EventBus,
+        listeners: Arc<Listeners>,
+    ) -> Self {
+        let current = listeners.current.fetch_add(1, Ordering::Relaxed) + 1;
+        listeners.peak.fetch_max(current, Ordering::Relaxed);
+        tracing::info!(
+            "Listener {} connected from {}",
+            id,
+            address.as_deref().unwrap_or("unknown")
+        );
+        events.publish(Event::ListenerConnected {
+            id,
+            address: address.clone(),
+        });
+        Self {
+            id,
+            address,
+            since: Instant::now(),
+            events,
+            listeners,
+        }
+    }
+}
+
+/// The body of a stream response, keeping its listener connected while the
+/// client receives it.
+struct ListenerStream<S> {
+    inner: S,
+    _listener: Listener,
+}
+
+impl<S: Stream + Unpin> Stream for ListenerStream<S> {
+    type Item = S::Item;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        Pin::new(&mut self.inner).poll_next(cx)
+    }
+}
+
+impl Drop for Listener {
+    fn drop(&mut self) {
+        tracing::info!("Listener {} disconnected", self.id);
+        self.listeners.current.fetch_sub(1, Ordering::Relaxed);
+        self.events.publish(Event::ListenerDisconnected {
+            id: self.id,
+            address: self.address.take(),
+            duration: self.since.elapsed().as_secs_f64(),
+        });
+    }
 }
 
 impl StreamerBackend {
-    pub fn start() -> anyhow::Result<(Self, Manager)> {
+    pub fn start(events: EventBus) -> anyhow::Result<(Self, Manager)> {
         let (manager, mut renderer) = Manager::new();
         renderer.set_output_channel_count_and_sample_rate(CHANNEL_COUNT as u16, SAMPLE_RATE as u32);
 
@@ -66,29 +154,43 @@ impl StreamerBackend {
             }
         });
 
-        Ok((Self { stream_receiver }, manager))
+        Ok((
+            Self {
+                stream_receiver,
+                events,
+                next_listener_id: Default::default(),
+                listeners: Default::default(),
+            },
+            manager,
+        ))
     }
 }
 
-impl Clone for StreamerBackend {
-    fn clone(&self) -> Self {
-        Self {
-            stream_receiver: self.stream_receiver.clone(),
-        }
+impl StreamerBackend {
+    /// Receive the rendered PCM of the station.
+    pub fn subscribe(&self) -> Receiver<Box<Chunk>> {
+        self.stream_receiver.clone()
     }
-}
 
-impl Service<Request<body::Incoming>> for StreamerBackend {
-    type Response = Response<BoxBody<Bytes, anyhow::Error>>;
+    /// Clients currently receiving the MP3 stream.
+    pub fn listeners(&self) -> usize {
+        self.listeners.current.load(Ordering::Relaxed)
+    }
 
-    type Error = anyhow::Error;
+    /// Most clients receiving the MP3 stream at once since the start.
+    pub fn listener_peak(&self) -> usize {
+        self.listeners.peak.load(Ordering::Relaxed)
+    }
+}
 
-    type Future = std::pin::Pin<
-        Box<dyn std::future::Future<Output = Result<Self::Response, Self::Error>> + Send>,
-    >;
+/// Encodes the rendered PCM to MP3, one encoder per listener or output.
+pub struct Mp3Encoder {
+    encoder: mp3lame_encoder::Encoder,
+}
 
-    fn call(&self, _req: Request<body::Incoming>) -> Self::Future {
-        use mp3lame_encoder::{Builder, InterleavedPcm};
+impl Mp3Encoder {
+    pub fn new() -> Self {
+        use mp3lame_encoder::Builder;
 
         let mut mp3_encoder = Builder::new().expect("Create LAME builder");
         mp3_encoder
@@ -103,32 +205,77 @@ impl Service<Request<body::Incoming>> for StreamerBackend {
         mp3_encoder
             .set_quality(mp3lame_encoder::Quality::Best)
             .expect("set quality");
-        let mut mp3_encoder = mp3_encoder.build().expect("To initialize LAME encoder");
+        let encoder = mp3_encoder.build().expect("To initialize LAME encoder");
+        Self { encoder }
+    }
+
+    pub fn encode(&mut self, data: &Chunk) -> Bytes {
+        use mp3lame_encoder::InterleavedPcm;
+
+        let input = InterleavedPcm(&data.as_slice());
+        let mut mp3_out_buffer: Vec<u8> = Vec::new();
+        mp3_out_buffer.reserve(mp3lame_encoder::max_required_buffer_size(data.len() / 2));
+        let encoded_size = self
+            .encoder
+            .encode(input, mp3_out_buffer.spare_capacity_mut())
+            .expect("To encode");
+        unsafe {
+            mp3_out_buffer.set_len(mp3_out_buffer.len().wrapping_add(encoded_size));
+        }
+        Bytes::from(mp3_out_buffer)
+    }
+}
+
+impl Clone for StreamerBackend {
+    fn clone(&self) -> Self {
+        Self {
+            stream_receiver: self.stream_receiver.clone(),
+            events: self.events.clone(),
+            next_listener_id: self.next_listener_id.clone(),
+            listeners: self.listeners.clone(),
+        }
+    }
+}
+
+impl Service<Request<body::Incoming>> for StreamerBackend {
+    type Response = Response<BoxBody<Bytes, anyhow::Error>>;
+
+    type Error = anyhow::Error;
+
+    type Future = std::pin::Pin<
+        Box<dyn std::future::Future<Output = Result<Self::Response, Self::Error>> + Send>,
+    >;
+
+    fn call(&self, req: Request<body::Incoming>) -> Self::Future {
+        let mut mp3_encoder = Mp3Encoder::new();
+        let listener = Listener::connect(
+            self.next_listener_id.fetch_add(1, Ordering::Relaxed),
+            req.extensions().get::<SocketAddr>().map(|a| a.to_string()),
+            self.events.clone(),
+            self.listeners.clone(),
+        );
 
         //use actual PCM data
-        let watch_stream = self.stream_receiver.clone().map(move |data| {
-            let input = InterleavedPcm(&data.as_slice());
-            let mut mp3_out_buffer: Vec<u8> = Vec::new();
-            mp3_out_buffer.reserve(mp3lame_encoder::max_required_buffer_size(data.len() / 2));
-            let encoded_size = mp3_encoder
-                .encode(input, mp3_out_buffer.spare_capacity_mut())
-                .expect("To encode");
-            unsafe {
-                mp3_out_buffer.set_len(mp3_out_buffer.len().wrapping_add(encoded_size));
-            }
-            anyhow::Ok(Bytes::from(mp3_out_buffer))
-        });
+        let watch_stream = self
+            .subscribe()
+            .map(move |data| anyhow::Ok(mp3_encoder.encode(&data)));
+        let watch_stream = ListenerStream {
+            inner: watch_stream,
+            _listener: listener,
+        };
 
         let stream_body = StreamBody::new(watch_stream.map_ok(Frame::data));
 
         let boxed_body: BoxBody<Bytes, anyhow::Error> = BoxBody::new(stream_body); //.boxed();
-        Box::pin(async {
-            anyhow::Ok(
-                Response::builder()
-                    .status(StatusCode::OK)
-                    .body(boxed_body)
-                    .unwrap(),
-            )
-        })
+        Box::pin(async { anyhow::Ok(response(boxed_body)) })
     }
 }
+
+/// A stream response with the given body.
+pub fn response(body: BoxBody<Bytes, anyhow::Error>) -> Response<BoxBody<Bytes, anyhow::Error>> {
+    Response::builder()
+        .status(StatusCode::OK)
+        .header(hyper::header::CONTENT_TYPE, "audio/mpeg")
+        .body(body)
+        .unwrap()
+}
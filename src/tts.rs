@@ -0,0 +1,141 @@
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::Arc;
+
+use tokio::sync::broadcast::error::RecvError;
+
+use crate::interstitial;
+use crate::jellyfin::Audio;
+use crate::player::{PlayerController, PlayerEvent, Track};
+
+/// Placeholder in the command for the text to speak.
+const TEXT_PLACEHOLDER: &str = "{text}";
+/// Placeholder in the command for the WAV file to write.
+const OUTPUT_PLACEHOLDER: &str = "{output}";
+
+static NEXT_FILE: AtomicU64 = AtomicU64::new(0);
+
+/// A local text to speech program, e.g. `espeak-ng -w {output} {text}`.
+/// The command is split on whitespace and run without a shell, so track
+/// names can't inject anything. Without a `{text}` placeholder the text is
+/// written to stdin instead, as piper expects.
+pub struct Speaker {
+    command: Vec<String>,
+}
+
+impl Speaker {
+    pub fn new(command: &str) -> anyhow::Result<Self> {
+        let command: Vec<String> = command.split_whitespace().map(str::to_owned).collect();
+        if command.is_empty() {
+            anyhow::bail!("TTS command is empty");
+        }
+        if !command.iter().any(|arg| arg.contains(OUTPUT_PLACEHOLDER)) {
+            anyhow::bail!("TTS command has no {} placeholder", OUTPUT_PLACEHOLDER);
+        }
+        Ok(Self { command })
+    }
+
+    /// Speak `text` into a new WAV file and return its path.
+    pub async fn speak(&self, text: &str) -> anyhow::Result<PathBuf> {
+        let output = std::env::temp_dir().join(format!(
+            "jellyfin-radio-{}-{}.wav",
+            std::process::id(),
+            NEXT_FILE.fetch_add(1, Ordering::Relaxed)
+        ));
+        let output_str = output.display().to_string();
+        let args: Vec<String> = self
+            .command
+            .iter()
+            .map(|arg| {
+                arg.replace(OUTPUT_PLACEHOLDER, &output_str)
+                    .replace(TEXT_PLACEHOLDER, text)
+            })
+            .collect();
+
+        let mut command = tokio::process::Command::new(&args[0]);
+        command.args(&args[1..]).kill_on_drop(true);
+        if !self
+            .command
+            .iter()
+            .any(|arg| arg.contains(TEXT_PLACEHOLDER))
+        {
+            command.stdin(std::process::Stdio::piped());
+        }
+        let mut child = command.spawn()?;
+        if let Some(mut stdin) = child.stdin.take() {
+            use tokio::io::AsyncWriteExt;
+            stdin.write_all(text.as_bytes()).await?;
+        }
+        let status = child.wait().await?;
+        if !status.success() {
+            anyhow::bail!("TTS command failed with {}", status);
+        }
+        Ok(output)
+    }
+}
+
+/// What was played and what comes next, as a radio host would say it.
+fn announcement(previous: &Audio, next: Option<&Audio>) -> String {
+    let mut text = format!("That was {}", describe(previous));
+    if let Some(next) = next {
+        text.push_str(&format!(". Up next, {}", describe(next)));
+    }
+    text.push('.');
+    text
+}
+
+fn describe(audio: &Audio) -> String {
+    if audio.artists.is_empty() {
+        audio.name.clone()
+    } else {
+        format!("{} by {}", audio.name, audio.artists.join(" and "))
+    }
+}
+
+/// Queue a spoken announcement after every `every_songs` songs.
+pub async fn run(
+    speaker: Speaker,
+    every_songs: u32,
+    mut player_controller: PlayerController,
+    live_on_air: Arc<AtomicBool>,
+) {
+    let mut events = player_controller.subscribe();
+    let mut songs_since_announcement = 0;
+
+    loop {
+        let event = match events.recv().await {
+            Ok(event) => event,
+            Err(RecvError::Lagged(_)) => continue,
+            Err(RecvError::Closed) => return,
+        };
+        let PlayerEvent::Started(Track::Song(current)) = event else {
+            continue;
+        };
+        songs_since_announcement += 1;
+        // Queued now, it would play right after the live source
+        if songs_since_announcement < every_songs || live_on_air.load(Ordering::SeqCst) {
+            continue;
+        }
+        songs_since_announcement = 0;
+
+        // Spoken after the current song, so it is the one which was just heard
+        let next = player_controller.upcoming_song().await;
+        let text = announcement(&current, next.as_ref());
+        tracing::info!("Speaking {:?}", text);
+        let path = match speaker.speak(&text).await {
+            Ok(path) => path,
+            Err(e) => {
+                tracing::error!("Error generating announcement: {}", e);
+                continue;
+            }
+        };
+        match interstitial::load_sound(&path) {
+            Ok(sound) => player_controller.insert_next(sound, Track::Interstitial(text)),
+            Err(e) => tracing::error!("Error loading announcement {:?}: {}", path, e),
+        }
+        // The decoder keeps the file open, so it can be removed already
+        if let Err(e) = std::fs::remove_file(&path) {
+            tracing::warn!("Could not remove announcement {:?}: {}", path, e);
+        }
+    }
+}
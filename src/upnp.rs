@@ -0,0 +1,530 @@
+use std::net::{Ipv4Addr, SocketAddr};
+use std::time::Duration;
+
+use bytes::Bytes;
+use http_body_util::combinators::BoxBody;
+use http_body_util::{BodyExt, Limited};
+use hyper::{body, header, Request, Response, StatusCode};
+use tokio::net::UdpSocket;
+
+use crate::server::{respond, respond_with};
+use crate::streamer;
+
+pub const SSDP_ADDR: Ipv4Addr = Ipv4Addr::new(239, 255, 255, 250);
+pub const SSDP_PORT: u16 = 1900;
+/// How long control points may cache an advertisement, in seconds.
+const MAX_AGE: u32 = 1800;
+/// Advertisements are repeated well within `MAX_AGE`.
+const NOTIFY_INTERVAL: Duration = Duration::from_secs(600);
+const MAX_SOAP_SIZE: usize = 64 * 1024;
+
+const DEVICE_TYPE: &str = "urn:schemas-upnp-org:device:MediaServer:1";
+const CONTENT_DIRECTORY: &str = "urn:schemas-upnp-org:service:ContentDirectory:1";
+const CONNECTION_MANAGER: &str = "urn:schemas-upnp-org:service:ConnectionManager:1";
+
+const DESCRIPTION_PATH: &str = "/upnp/description.xml";
+const STREAM_ID: &str = "stream";
+const PROTOCOL_INFO: &str = "http-get:*:audio/mpeg:DLNA.ORG_PN=MP3;DLNA.ORG_OP=00;DLNA.ORG_CI=0;DLNA.ORG_FLAGS=01700000000000000000000000000000";
+
+type Body = BoxBody<Bytes, anyhow::Error>;
+
+/// Advertises the station over SSDP as a UPnP MediaServer, whose
+/// ContentDirectory lists the stream.
+#[derive(Clone)]
+pub struct MediaServer {
+    name: String,
+    uuid: String,
+    port: u16,
+}
+
+impl MediaServer {
+    /// `seed` keeps the device id the same across restarts.
+    pub fn new(name: String, seed: &str, port: u16) -> Self {
+        Self {
+            name,
+            uuid: uuid(seed),
+            port,
+        }
+    }
+
+    /// Notification types with their unique service names.
+    fn notification_types(&self) -> Vec<(String, String)> {
+        let udn = format!("uuid:{}", self.uuid);
+        let mut types = vec![
+            (
+                "upnp:rootdevice".to_owned(),
+                format!("{udn}::upnp:rootdevice"),
+            ),
+            (udn.clone(), udn.clone()),
+        ];
+        for kind in [DEVICE_TYPE, CONTENT_DIRECTORY, CONNECTION_MANAGER] {
+            types.push((kind.to_owned(), format!("{udn}::{kind}")));
+        }
+        types
+    }
+
+    fn location(&self, ip: Ipv4Addr) -> String {
+        format!("http://{ip}:{}{DESCRIPTION_PATH}", self.port)
+    }
+
+    /// Answer searches and periodically announce the device.
+    pub async fn advertise(self) -> anyhow::Result<()> {
+        let socket = socket2::Socket::new(
+            socket2::Domain::IPV4,
+            socket2::Type::DGRAM,
+            Some(socket2::Protocol::UDP),
+        )?;
+        // Other UPnP software on the host listens on the same port
+        socket.set_reuse_address(true)?;
+        socket.set_nonblocking(true)?;
+        socket.bind(&SocketAddr::from((Ipv4Addr::UNSPECIFIED, SSDP_PORT)).into())?;
+        socket.join_multicast_v4(&SSDP_ADDR, &Ipv4Addr::UNSPECIFIED)?;
+        let socket = UdpSocket::from_std(socket.into())?;
+        tracing::info!("Advertising {} over SSDP", self.name);
+
+        let mut notify = tokio::time::interval(NOTIFY_INTERVAL);
+        let mut buffer = [0; 2048];
+        loop {
+            tokio::select! {
+                received = socket.recv_from(&mut buffer) => {
+                    // Errors like ICMP unreachables from earlier answers
+                    // only concern that one peer
+                    let (size, peer) = match received {
+                        Ok(received) => received,
+                        Err(e) => {
+                            tracing::warn!("Error receiving SSDP message: {}", e);
+                            continue;
+                        }
+                    };
+                    let message = String::from_utf8_lossy(&buffer[..size]);
+                    if let Err(e) = self.answer_search(&socket, &message, peer).await {
+                        tracing::debug!("Error answering SSDP search from {}: {}", peer, e);
+                    }
+                }
+                _ = notify.tick() => {
+                    if let Err(e) = self.notify(&socket).await {
+                        tracing::warn!("Error sending SSDP announcement: {}", e);
+                    }
+                }
+            }
+        }
+    }
+
+    async fn answer_search(
+        &self,
+        socket: &UdpSocket,
+        message: &str,
+        peer: SocketAddr,
+    ) -> anyhow::Result<()> {
+        let mut lines = message.lines();
+        if !lines
+            .next()
+            .is_some_and(|l| l.starts_with("M-SEARCH * HTTP/1.1"))
+        {
+            return Ok(());
+        }
+        let Some(search_target) = lines.find_map(|line| {
+            let (name, value) = line.split_once(':')?;
+            name.trim()
+                .eq_ignore_ascii_case("ST")
+                .then(|| value.trim().to_owned())
+        }) else {
+            return Ok(());
+        };
+
+        let ip = local_ip_towards(peer)?;
+        for (nt, usn) in self.notification_types() {
+            if search_target != "ssdp:all" && search_target != nt {
+                continue;
+            }
+            let response = format!(
+                "HTTP/1.1 200 OK\r\n\
+                 CACHE-CONTROL: max-age={MAX_AGE}\r\n\
+                 EXT:\r\n\
+                 LOCATION: {}\r\n\
+                 SERVER: {}\r\n\
+                 ST: {nt}\r\n\
+                 USN: {usn}\r\n\r\n",
+                self.location(ip),
+                server_header(),
+            );
+            socket.send_to(response.as_bytes(), peer).await?;
+        }
+        Ok(())
+    }
+
+    async fn notify(&self, socket: &UdpSocket) -> anyhow::Result<()> {
+        let group = SocketAddr::from((SSDP_ADDR, SSDP_PORT));
+        let ip = local_ip_towards(group)?;
+        for (nt, usn) in self.notification_types() {
+            let message = format!(
+                "NOTIFY * HTTP/1.1\r\n\
+                 HOST: {SSDP_ADDR}:{SSDP_PORT}\r\n\
+                 CACHE-CONTROL: max-age={MAX_AGE}\r\n\
+                 LOCATION: {}\r\n\
+                 NT: {nt}\r\n\
+                 NTS: ssdp:alive\r\n\
+                 SERVER: {}\r\n\
+                 USN: {usn}\r\n\r\n",
+                self.location(ip),
+                server_header(),
+            );
+            socket.send_to(message.as_bytes(), group).await?;
+        }
+        Ok(())
+    }
+
+    /// Serve the device description, service descriptions and controls.
+    pub async fn handle(self, req: Request<body::Incoming>) -> anyhow::Result<Response<Body>> {
+        let xml = "text/xml; charset=\"utf-8\"";
+        match (req.method().as_str(), req.uri().path()) {
+            ("GET", DESCRIPTION_PATH) => Ok(respond_with(StatusCode::OK, xml, self.description())),
+            ("GET", "/upnp/ContentDirectory.xml") => {
+                Ok(respond_with(StatusCode::OK, xml, CONTENT_DIRECTORY_SCPD))
+            }
+            ("GET", "/upnp/ConnectionManager.xml") => {
+                Ok(respond_with(StatusCode::OK, xml, CONNECTION_MANAGER_SCPD))
+            }
+            ("POST", "/upnp/control/ContentDirectory") => self.content_directory(req).await,
+            ("POST", "/upnp/control/ConnectionManager") => self.connection_manager(req).await,
+            // Nothing ever changes, so subscribers just never get an event
+            ("SUBSCRIBE", _) => Ok(Response::builder()
+                .status(StatusCode::OK)
+                .header(
+                    "SID",
+                    format!("uuid:{}", uuid(&rand::random::<u64>().to_string())),
+                )
+                .header("TIMEOUT", format!("Second-{MAX_AGE}"))
+                .body(BoxBody::default())
+                .unwrap()),
+            ("UNSUBSCRIBE", _) => Ok(respond(StatusCode::OK, "")),
+            _ => Ok(respond(StatusCode::NOT_FOUND, "Not found")),
+        }
+    }
+
+    fn description(&self) -> String {
+        let service = |kind: &str, name: &str| {
+            format!(
+                "<service>\
+                 <serviceType>{kind}</serviceType>\
+                 <serviceId>urn:upnp-org:serviceId:{name}</serviceId>\
+                 <SCPDURL>/upnp/{name}.xml</SCPDURL>\
+                 <controlURL>/upnp/control/{name}</controlURL>\
+                 <eventSubURL>/upnp/event/{name}</eventSubURL>\
+                 </service>"
+            )
+        };
+        format!(
+            "<?xml version=\"1.0\" encoding=\"utf-8\"?>\
+             <root xmlns=\"urn:schemas-upnp-org:device-1-0\">\
+             <specVersion><major>1</major><minor>0</minor></specVersion>\
+             <device>\
+             <deviceType>{DEVICE_TYPE}</deviceType>\
+             <friendlyName>{}</friendlyName>\
+             <manufacturer>jellyfin-radio</manufacturer>\
+             <modelName>jellyfin-radio</modelName>\
+             <modelNumber>{}</modelNumber>\
+             <UDN>uuid:{}</UDN>\
+             <serviceList>{}{}</serviceList>\
+             </device>\
+             </root>",
+            escape(&self.name),
+            env!("CARGO_PKG_VERSION"),
+            self.uuid,
+            service(CONTENT_DIRECTORY, "ContentDirectory"),
+            service(CONNECTION_MANAGER, "ConnectionManager"),
+        )
+    }
+
+    async fn content_directory(
+        &self,
+        req: Request<body::Incoming>,
+    ) -> anyhow::Result<Response<Body>> {
+        // The stream is served by us, under the address the client used
+        let host = req
+            .headers()
+            .get(header::HOST)
+            .and_then(|v| v.to_str().ok())
+            .map(str::to_owned)
+            .unwrap_or_else(|| format!("localhost:{}", self.port));
+        let (action, body) = soap_request(req).await?;
+
+        let response = match action.as_str() {
+            "Browse" => {
+                let object_id = argument(&body, "ObjectID").unwrap_or_default();
+                let flag = argument(&body, "BrowseFlag").unwrap_or_default();
+                let url = format!("http://{host}{}", streamer::MOUNT);
+                let (didl, count) = match (object_id.as_str(), flag.as_str()) {
+                    ("0", "BrowseMetadata") => (self.root_container(), 1),
+                    ("0", "BrowseDirectChildren") => (stream_item(&self.name, &url), 1),
+                    (STREAM_ID, "BrowseMetadata") => (stream_item(&self.name, &url), 1),
+                    (STREAM_ID, "BrowseDirectChildren") => (String::new(), 0),
+                    _ => return Ok(soap_fault(701, "No such object")),
+                };
+                format!(
+                    "<Result>{}</Result>\
+                     <NumberReturned>{count}</NumberReturned>\
+                     <TotalMatches>{count}</TotalMatches>\
+                     <UpdateID>1</UpdateID>",
+                    escape(&didl_lite(&didl))
+                )
+            }
+            "GetSearchCapabilities" => "<SearchCaps></SearchCaps>".to_owned(),
+            "GetSortCapabilities" => "<SortCaps></SortCaps>".to_owned(),
+            "GetSystemUpdateID" => "<Id>1</Id>".to_owned(),
+            _ => return Ok(soap_fault(401, "Invalid Action")),
+        };
+        Ok(soap_response(CONTENT_DIRECTORY, &action, &response))
+    }
+
+    async fn connection_manager(
+        &self,
+        req: Request<body::Incoming>,
+    ) -> anyhow::Result<Response<Body>> {
+        let (action, _) = soap_request(req).await?;
+        let response = match action.as_str() {
+            "GetProtocolInfo" => format!("<Source>{PROTOCOL_INFO}</Source><Sink></Sink>"),
+            "GetCurrentConnectionIDs" => "<ConnectionIDs>0</ConnectionIDs>".to_owned(),
+            "GetCurrentConnectionInfo" => "<RcsID>-1</RcsID>\
+                 <AVTransportID>-1</AVTransportID>\
+                 <ProtocolInfo></ProtocolInfo>\
+                 <PeerConnectionManager></PeerConnectionManager>\
+                 <PeerConnectionID>-1</PeerConnectionID>\
+                 <Direction>Output</Direction>\
+                 <Status>OK</Status>"
+                .to_owned(),
+            _ => return Ok(soap_fault(401, "Invalid Action")),
+        };
+        Ok(soap_response(CONNECTION_MANAGER, &action, &response))
+    }
+
+    fn root_container(&self) -> String {
+        format!(
+            "<container id=\"0\" parentID=\"-1\" childCount=\"1\" restricted=\"1\">\
+             <dc:title>{}</dc:title>\
+             <upnp:class>object.container</upnp:class>\
+             </container>",
+            escape(&self.name)
+        )
+    }
+}
+
+fn stream_item(name: &str, url: &str) -> String {
+    format!(
+        "<item id=\"{STREAM_ID}\" parentID=\"0\" restricted=\"1\">\
+         <dc:title>{}</dc:title>\
+         <upnp:class>object.item.audioItem.audioBroadcast</upnp:class>\
+         <res protocolInfo=\"{PROTOCOL_INFO}\">{}</res>\
+         </item>",
+        escape(name),
+        escape(url)
+    )
+}
+
+/// DIDL-Lite metadata of the stream at `url`, as renderers expect it
+/// along with the URL.
+pub fn stream_metadata(name: &str, url: &str) -> String {
+    didl_lite(&stream_item(name, url))
+}
+
+fn didl_lite(content: &str) -> String {
+    format!(
+        "<DIDL-Lite xmlns=\"urn:schemas-upnp-org:metadata-1-0/DIDL-Lite/\" \
+         xmlns:dc=\"http://purl.org/dc/elements/1.1/\" \
+         xmlns:upnp=\"urn:schemas-upnp-org:metadata-1-0/upnp/\">\
+         {content}\
+         </DIDL-Lite>"
+    )
+}
+
+/// The action from the `SOAPACTION` header and the request body.
+async fn soap_request(req: Request<body::Incoming>) -> anyhow::Result<(String, String)> {
+    let action = req
+        .headers()
+        .get("SOAPACTION")
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.trim_matches('"').rsplit_once('#'))
+        .map(|(_, action)| action.to_owned())
+        .unwrap_or_default();
+    let body = Limited::new(req.into_body(), MAX_SOAP_SIZE)
+        .collect()
+        .await
+        .map_err(|e| anyhow::anyhow!("Error reading SOAP request: {}", e))?
+        .to_bytes();
+    Ok((action, String::from_utf8_lossy(&body).into_owned()))
+}
+
+/// The text of the first `<name>` element. Good enough for the flat
+/// arguments of UPnP actions.
+pub fn argument(body: &str, name: &str) -> Option<String> {
+    let start = body.find(&format!("<{name}>"))? + name.len() + 2;
+    let end = start + body[start..].find(&format!("</{name}>"))?;
+    Some(unescape(body[start..end].trim()))
+}
+
+pub fn soap_envelope(body: &str) -> String {
+    format!(
+        "<?xml version=\"1.0\" encoding=\"utf-8\"?>\
+         <s:Envelope xmlns:s=\"http://schemas.xmlsoap.org/soap/envelope/\" \
+         s:encodingStyle=\"http://schemas.xmlsoap.org/soap/encoding/\">\
+         <s:Body>{body}</s:Body>\
+         </s:Envelope>"
+    )
+}
+
+fn soap_response(service: &str, action: &str, arguments: &str) -> Response<Body> {
+    let body = soap_envelope(&format!(
+        "<u:{action}Response xmlns:u=\"{service}\">{arguments}</u:{action}Response>"
+    ));
+    respond_with(StatusCode::OK, "text/xml; charset=\"utf-8\"", body)
+}
+
+fn soap_fault(code: u32, description: &str) -> Response<Body> {
+    let body = soap_envelope(&format!(
+        "<s:Fault>\
+         <faultcode>s:Client</faultcode>\
+         <faultstring>UPnPError</faultstring>\
+         <detail>\
+         <UPnPError xmlns=\"urn:schemas-upnp-org:control-1-0\">\
+         <errorCode>{code}</errorCode>\
+         <errorDescription>{description}</errorDescription>\
+         </UPnPError>\
+         </detail>\
+         </s:Fault>"
+    ));
+    respond_with(
+        StatusCode::INTERNAL_SERVER_ERROR,
+        "text/xml; charset=\"utf-8\"",
+        body,
+    )
+}
+
+pub fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+        .replace('\'', "&apos;")
+}
+
+pub fn unescape(text: &str) -> String {
+    text.replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&quot;", "\"")
+        .replace("&apos;", "'")
+        .replace("&amp;", "&")
+}
+
+pub fn server_header() -> String {
+    format!(
+        "{}/1.0 UPnP/1.0 jellyfin-radio/{}",
+        std::env::consts::OS,
+        env!("CARGO_PKG_VERSION")
+    )
+}
+
+/// The address of this host on the route towards `peer`.
+pub fn local_ip_towards(peer: SocketAddr) -> anyhow::Result<Ipv4Addr> {
+    let socket = std::net::UdpSocket::bind((Ipv4Addr::UNSPECIFIED, 0))?;
+    socket.connect(peer)?;
+    match socket.local_addr()?.ip() {
+        std::net::IpAddr::V4(ip) => Ok(ip),
+        std::net::IpAddr::V6(ip) => anyhow::bail!("Unexpected IPv6 address {}", ip),
+    }
+}
+
+/// A UUID derived from `seed` with two rounds of FNV-1a.
+fn uuid(seed: &str) -> String {
+    let hash = |offset: u64| {
+        seed.bytes().fold(offset, |hash, byte| {
+            (hash ^ byte as u64).wrapping_mul(0x100000001b3)
+        })
+    };
+    let value = (hash(0xcbf29ce484222325) as u128) << 64 | hash(0x84222325cbf29ce4) as u128;
+    let hex = format!("{value:032x}");
+    format!(
+        "{}-{}-{}-{}-{}",
+        &hex[0..8],
+        &hex[8..12],
+        &hex[12..16],
+        &hex[16..20],
+        &hex[20..32]
+    )
+}
+
+const CONTENT_DIRECTORY_SCPD: &str = r#"<?xml version="1.0" encoding="utf-8"?>
+<scpd xmlns="urn:schemas-upnp-org:service-1-0">
+<specVersion><major>1</major><minor>0</minor></specVersion>
+<actionList>
+<action><name>Browse</name><argumentList>
+<argument><name>ObjectID</name><direction>in</direction><relatedStateVariable>A_ARG_TYPE_ObjectID</relatedStateVariable></argument>
+<argument><name>BrowseFlag</name><direction>in</direction><relatedStateVariable>A_ARG_TYPE_BrowseFlag</relatedStateVariable></argument>
+<argument><name>Filter</name><direction>in</direction><relatedStateVariable>A_ARG_TYPE_Filter</relatedStateVariable></argument>
+<argument><name>StartingIndex</name><direction>in</direction><relatedStateVariable>A_ARG_TYPE_Index</relatedStateVariable></argument>
+<argument><name>RequestedCount</name><direction>in</direction><relatedStateVariable>A_ARG_TYPE_Count</relatedStateVariable></argument>
+<argument><name>SortCriteria</name><direction>in</direction><relatedStateVariable>A_ARG_TYPE_SortCriteria</relatedStateVariable></argument>
+<argument><name>Result</name><direction>out</direction><relatedStateVariable>A_ARG_TYPE_Result</relatedStateVariable></argument>
+<argument><name>NumberReturned</name><direction>out</direction><relatedStateVariable>A_ARG_TYPE_Count</relatedStateVariable></argument>
+<argument><name>TotalMatches</name><direction>out</direction><relatedStateVariable>A_ARG_TYPE_Count</relatedStateVariable></argument>
+<argument><name>UpdateID</name><direction>out</direction><relatedStateVariable>A_ARG_TYPE_UpdateID</relatedStateVariable></argument>
+</argumentList></action>
+<action><name>GetSearchCapabilities</name><argumentList>
+<argument><name>SearchCaps</name><direction>out</direction><relatedStateVariable>SearchCapabilities</relatedStateVariable></argument>
+</argumentList></action>
+<action><name>GetSortCapabilities</name><argumentList>
+<argument><name>SortCaps</name><direction>out</direction><relatedStateVariable>SortCapabilities</relatedStateVariable></argument>
+</argumentList></action>
+<action><name>GetSystemUpdateID</name><argumentList>
+<argument><name>Id</name><direction>out</direction><relatedStateVariable>SystemUpdateID</relatedStateVariable></argument>
+</argumentList></action>
+</actionList>
+<serviceStateTable>
+<stateVariable sendEvents="no"><name>A_ARG_TYPE_ObjectID</name><dataType>string</dataType></stateVariable>
+<stateVariable sendEvents="no"><name>A_ARG_TYPE_BrowseFlag</name><dataType>string</dataType><allowedValueList><allowedValue>BrowseMetadata</allowedValue><allowedValue>BrowseDirectChildren</allowedValue></allowedValueList></stateVariable>
+<stateVariable sendEvents="no"><name>A_ARG_TYPE_Filter</name><dataType>string</dataType></stateVariable>
+<stateVariable sendEvents="no"><name>A_ARG_TYPE_Index</name><dataType>ui4</dataType></stateVariable>
+<stateVariable sendEvents="no"><name>A_ARG_TYPE_Count</name><dataType>ui4</dataType></stateVariable>
+<stateVariable sendEvents="no"><name>A_ARG_TYPE_SortCriteria</name><dataType>string</dataType></stateVariable>
+<stateVariable sendEvents="no"><name>A_ARG_TYPE_Result</name><dataType>string</dataType></stateVariable>
+<stateVariable sendEvents="no"><name>A_ARG_TYPE_UpdateID</name><dataType>ui4</dataType></stateVariable>
+<stateVariable sendEvents="no"><name>SearchCapabilities</name><dataType>string</dataType></stateVariable>
+<stateVariable sendEvents="no"><name>SortCapabilities</name><dataType>string</dataType></stateVariable>
+<stateVariable sendEvents="yes"><name>SystemUpdateID</name><dataType>ui4</dataType></stateVariable>
+</serviceStateTable>
+</scpd>"#;
+
+const CONNECTION_MANAGER_SCPD: &str = r#"<?xml version="1.0" encoding="utf-8"?>
+<scpd xmlns="urn:schemas-upnp-org:service-1-0">
+<specVersion><major>1</major><minor>0</minor></specVersion>
+<actionList>
+<action><name>GetProtocolInfo</name><argumentList>
+<argument><name>Source</name><direction>out</direction><relatedStateVariable>SourceProtocolInfo</relatedStateVariable></argument>
+<argument><name>Sink</name><direction>out</direction><relatedStateVariable>SinkProtocolInfo</relatedStateVariable></argument>
+</argumentList></action>
+<action><name>GetCurrentConnectionIDs</name><argumentList>
+<argument><name>ConnectionIDs</name><direction>out</direction><relatedStateVariable>CurrentConnectionIDs</relatedStateVariable></argument>
+</argumentList></action>
+<action><name>GetCurrentConnectionInfo</name><argumentList>
+<argument><name>ConnectionID</name><direction>in</direction><relatedStateVariable>A_ARG_TYPE_ConnectionID</relatedStateVariable></argument>
+<argument><name>RcsID</name><direction>out</direction><relatedStateVariable>A_ARG_TYPE_RcsID</relatedStateVariable></argument>
+<argument><name>AVTransportID</name><direction>out</direction><relatedStateVariable>A_ARG_TYPE_AVTransportID</relatedStateVariable></argument>
+<argument><name>ProtocolInfo</name><direction>out</direction><relatedStateVariable>A_ARG_TYPE_ProtocolInfo</relatedStateVariable></argument>
+<argument><name>PeerConnectionManager</name><direction>out</direction><relatedStateVariable>A_ARG_TYPE_ConnectionManager</relatedStateVariable></argument>
+<argument><name>PeerConnectionID</name><direction>out</direction><relatedStateVariable>A_ARG_TYPE_ConnectionID</relatedStateVariable></argument>
+<argument><name>Direction</name><direction>out</direction><relatedStateVariable>A_ARG_TYPE_Direction</relatedStateVariable></argument>
+<argument><name>Status</name><direction>out</direction><relatedStateVariable>A_ARG_TYPE_ConnectionStatus</relatedStateVariable></argument>
+</argumentList></action>
+</actionList>
+<serviceStateTable>
+<stateVariable sendEvents="yes"><name>SourceProtocolInfo</name><dataType>string</dataType></stateVariable>
+<stateVariable sendEvents="yes"><name>SinkProtocolInfo</name><dataType>string</dataType></stateVariable>
+<stateVariable sendEvents="yes"><name>CurrentConnectionIDs</name><dataType>string</dataType></stateVariable>
+<stateVariable sendEvents="no"><name>A_ARG_TYPE_ConnectionStatus</name><dataType>string</dataType><allowedValueList><allowedValue>OK</allowedValue><allowedValue>ContentFormatMismatch</allowedValue><allowedValue>InsufficientBandwidth</allowedValue><allowedValue>UnreliableChannel</allowedValue><allowedValue>Unknown</allowedValue></allowedValueList></stateVariable>
+<stateVariable sendEvents="no"><name>A_ARG_TYPE_ConnectionManager</name><dataType>string</dataType></stateVariable>
+<stateVariable sendEvents="no"><name>A_ARG_TYPE_Direction</name><dataType>string</dataType><allowedValueList><allowedValue>Input</allowedValue><allowedValue>Output</allowedValue></allowedValueList></stateVariable>
+<stateVariable sendEvents="no"><name>A_ARG_TYPE_ProtocolInfo</name><dataType>string</dataType></stateVariable>
+<stateVariable sendEvents="no"><name>A_ARG_TYPE_ConnectionID</name><dataType>i4</dataType></stateVariable>
+<stateVariable sendEvents="no"><name>A_ARG_TYPE_AVTransportID</name><dataType>i4</dataType></stateVariable>
+<stateVariable sendEvents="no"><name>A_ARG_TYPE_RcsID</name><dataType>i4</dataType></stateVariable>
+</serviceStateTable>
+</scpd>"#;